@@ -0,0 +1,151 @@
+//! Variables textmate and visual studio code provide out of the box.
+use crate::*;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MONTH_NAMES: [&str; 12] = ["January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December"];
+const DAY_NAMES: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+
+/// Resolves the [standard variables](https://code.visualstudio.com/docs/editor/userdefinedsnippets#_variables) from the state of an editor.
+/// Variables whose state is absent are not found, so a [`Variable`] can fall back on something else.
+/// Date and time variables are in UTC.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuiltinResolver {
+	/// `TM_SELECTED_TEXT`
+	pub selected_text: Option<String>,
+	/// `TM_CURRENT_LINE`
+	pub current_line: Option<String>,
+	/// `TM_CURRENT_WORD`
+	pub current_word: Option<String>,
+	/// Zero based `TM_LINE_INDEX`, `TM_LINE_NUMBER` is one more.
+	pub line_index: Option<usize>,
+	/// `TM_FILEPATH`, from which `TM_FILENAME`, `TM_FILENAME_BASE` and `TM_DIRECTORY` are derived.
+	pub filepath: Option<PathBuf>,
+	/// `CLIPBOARD`
+	pub clipboard: Option<String>,
+	/// Time the `CURRENT_*` variables are of, the time of resolving when absent.
+	pub now: Option<SystemTime>
+}
+impl BuiltinResolver {
+	pub fn new() -> BuiltinResolver {
+		BuiltinResolver::default()
+	}
+	fn path_variable(&self, name: &str) -> Option<String> {
+		let path = self.filepath.as_ref()?;
+		let part = match name {
+			"TM_FILEPATH" => Some(path.as_os_str()),
+			"TM_FILENAME" => path.file_name(),
+			"TM_FILENAME_BASE" => path.file_stem(),
+			"TM_DIRECTORY" => path.parent().map(|parent| parent.as_os_str()),
+			_ => None
+		}?;
+		Some(part.to_string_lossy().into_owned())
+	}
+	/// Seconds since the unix epoch of the time the `CURRENT_*` variables are of.
+	fn seconds(&self) -> Option<u64> {
+		Some(self.now.unwrap_or_else(SystemTime::now).duration_since(UNIX_EPOCH).ok()?.as_secs())
+	}
+	fn time_variable(&self, name: &str) -> Option<String> {
+		let seconds = self.seconds()?;
+		let days = seconds / 86400;
+		let (year, month, day) = civil_from_days(days);
+		let weekday = DAY_NAMES[((days + 4) % 7) as usize];
+		let month_name = MONTH_NAMES[month as usize - 1];
+		Some(match name {
+			"CURRENT_YEAR" => year.to_string(),
+			"CURRENT_YEAR_SHORT" => format!("{:02}", year % 100),
+			"CURRENT_MONTH" => format!("{:02}", month),
+			"CURRENT_MONTH_NAME" => String::from(month_name),
+			"CURRENT_MONTH_NAME_SHORT" => String::from(&month_name[..3]),
+			"CURRENT_DATE" => format!("{:02}", day),
+			"CURRENT_DAY_NAME" => String::from(weekday),
+			"CURRENT_DAY_NAME_SHORT" => String::from(&weekday[..3]),
+			"CURRENT_HOUR" => format!("{:02}", seconds % 86400 / 3600),
+			"CURRENT_MINUTE" => format!("{:02}", seconds % 3600 / 60),
+			"CURRENT_SECOND" => format!("{:02}", seconds % 60),
+			"CURRENT_SECONDS_UNIX" => seconds.to_string(),
+			_ => return None
+		})
+	}
+}
+impl VariableResolver for BuiltinResolver {
+	fn resolve(&self, name: &str) -> Option<String> {
+		match name {
+			"TM_SELECTED_TEXT" => self.selected_text.clone(),
+			"TM_CURRENT_LINE" => self.current_line.clone(),
+			"TM_CURRENT_WORD" => self.current_word.clone(),
+			"TM_LINE_INDEX" => self.line_index.map(|index| index.to_string()),
+			"TM_LINE_NUMBER" => self.line_index.map(|index| (index + 1).to_string()),
+			"CLIPBOARD" => self.clipboard.clone(),
+			_ if name.starts_with("TM_") => self.path_variable(name),
+			_ if name.starts_with("CURRENT_") => self.time_variable(name),
+			_ => None
+		}
+	}
+	/// Time of `now` for every date variable, so formatting `CURRENT_DATE` as `%Y-%m-%d` gives the whole date.
+	fn timestamp(&self, name: &str) -> Option<u64> {
+		self.time_variable(name)?;
+		self.seconds()
+	}
+}
+
+/// Year, month and day of the month of a count of days since 1970-01-01.
+/// From [Howard Hinnant's date algorithms](https://howardhinnant.github.io/date_algorithms.html#civil_from_days).
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+	let z = days + 719468;
+	let era = z / 146097;
+	let doe = z % 146097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+	(year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+	#[test]
+	fn fixed_clock_dates() {
+		// 2024-02-29 13:05:09 UTC, a Thursday.
+		let resolver = Rc::new(BuiltinResolver {
+			now: Some(UNIX_EPOCH + Duration::from_secs(1709211909)),
+			..BuiltinResolver::new()
+		});
+		let resolve = |name: &str| {
+			let mut var = Variable::resolved_by(name, resolver.clone());
+			var.try_evaluate().map(|_| var.value)
+		};
+		assert_eq!(resolve("CURRENT_YEAR"), Ok(String::from("2024")));
+		assert_eq!(resolve("CURRENT_YEAR_SHORT"), Ok(String::from("24")));
+		assert_eq!(resolve("CURRENT_MONTH"), Ok(String::from("02")));
+		assert_eq!(resolve("CURRENT_MONTH_NAME_SHORT"), Ok(String::from("Feb")));
+		assert_eq!(resolve("CURRENT_DATE"), Ok(String::from("29")));
+		assert_eq!(resolve("CURRENT_DAY_NAME"), Ok(String::from("Thursday")));
+		assert_eq!(resolve("CURRENT_HOUR"), Ok(String::from("13")));
+		assert_eq!(resolve("CURRENT_MINUTE"), Ok(String::from("05")));
+		assert_eq!(resolve("CURRENT_SECOND"), Ok(String::from("09")));
+		assert_eq!(resolve("CURRENT_SECONDS_UNIX"), Ok(String::from("1709211909")));
+		assert!(resolve("CURRENT_NOTHING").is_err());
+		assert_eq!(civil_from_days(0), (1970, 1, 1));
+		assert_eq!(civil_from_days(10956), (1999, 12, 31));
+	}
+	#[test]
+	fn file_variables() {
+		let resolver = BuiltinResolver {
+			filepath: Some(PathBuf::from("/home/user/src/main.rs")),
+			line_index: Some(4),
+			..BuiltinResolver::new()
+		};
+		println!("{:?}", resolver);
+		assert_eq!(resolver.resolve("TM_FILENAME").as_deref(), Some("main.rs"));
+		assert_eq!(resolver.resolve("TM_FILENAME_BASE").as_deref(), Some("main"));
+		assert_eq!(resolver.resolve("TM_DIRECTORY").as_deref(), Some("/home/user/src"));
+		assert_eq!(resolver.resolve("TM_FILEPATH").as_deref(), Some("/home/user/src/main.rs"));
+		assert_eq!(resolver.resolve("TM_LINE_NUMBER").as_deref(), Some("5"));
+		assert_eq!(resolver.resolve("TM_SELECTED_TEXT"), None);
+	}
+}
@@ -0,0 +1,186 @@
+//! Writing snippets out in the snippet syntax of other editors.
+use crate::*;
+use std::fmt::Write;
+
+/// Escaped in text and placeholder defaults by visual studio code.
+const TEXT_SPECIAL: &[char] = &['$', '}', '\\'];
+/// Escaped in text and placeholder defaults by textmate, where a backtick starts shell code.
+const TEXTMATE_TEXT_SPECIAL: &[char] = &['$', '`', '}', '\\'];
+/// Escaped in the options of a choice by visual studio code.
+const CHOICE_SPECIAL: &[char] = &[',', '|', '\\'];
+/// Escaped within interpolated shell code.
+const CODE_SPECIAL: &[char] = &['`', '\\'];
+/// Escaped within the regex and format of a variable transform, the regex is otherwise written as is.
+#[cfg(feature = "regex")]
+const TRANSFORM_SPECIAL: &[char] = &['/'];
+
+fn escape(out: &mut String, text: &str, special: &[char]) {
+	for c in text.chars() {
+		if special.contains(&c) {
+			out.push('\\');
+		}
+		out.push(c);
+	}
+}
+
+/// Snippet syntax to write.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Dialect {
+	VsCode,
+	/// Language server protocol snippets, which have no shell code.
+	/// Programic segments are written as the text they have evaluated to.
+	Lsp,
+	/// Textmate snippets, whose transforms change case with `\u`, `\U` and `\L` in the format.
+	TextMate
+}
+
+/// Writes the body of a snippet.
+/// Fields are numbered by the tab they are the field of, fields that are not a tab are written as their text.
+/// Variables whose name can not be written in a snippet are written as their text too.
+/// A segment nested within itself is written as `…` where it is nested, as it is displayed.
+pub(crate) struct Exporter {
+	dialect: Dialect,
+	tabs: Vec<(*const (), usize)>,
+	ancestors: Vec<*const ()>
+}
+impl Exporter {
+	pub(crate) fn new(snippet: &Snippet, dialect: Dialect) -> Exporter {
+		Exporter {
+			dialect,
+			tabs: snippet.tabs.iter().map(|tab| (Rc::as_ptr(&tab.field) as *const (), tab.num)).collect(),
+			ancestors: vec![]
+		}
+	}
+	fn tab_num(&self, interactive: &Rc<RefCell<dyn InteractiveSegment>>) -> Option<usize> {
+		let pointer = Rc::as_ptr(interactive) as *const ();
+		self.tabs.iter().find(|(field, _)| *field == pointer).map(|(_, num)| *num)
+	}
+	fn text(&self, out: &mut String, text: &str) {
+		if self.dialect == Dialect::TextMate {
+			escape(out, text, TEXTMATE_TEXT_SPECIAL);
+		} else {
+			escape(out, text, TEXT_SPECIAL);
+		}
+	}
+	pub(crate) fn body(&mut self, segments: &[Segment]) -> String {
+		let mut out = String::new();
+		self.segments(&mut out, segments);
+		out
+	}
+	fn segments(&mut self, out: &mut String, segments: &[Segment]) {
+		for segment in segments {
+			match segment {
+				Segment::Text(text) => self.text(out, text),
+				Segment::Interactive(interactive) => self.interactive(out, interactive),
+				Segment::Reference(reference) => self.text(out, &reference.borrow().resolve())
+			}
+		}
+	}
+	fn interactive(&mut self, out: &mut String, rc: &Rc<RefCell<dyn InteractiveSegment>>) {
+		let pointer = Rc::as_ptr(rc) as *const ();
+		if self.ancestors.contains(&pointer) {
+			out.push('…');
+			return
+		}
+		self.ancestors.push(pointer);
+		self.nested(out, rc);
+		self.ancestors.pop();
+	}
+	fn nested(&mut self, out: &mut String, rc: &Rc<RefCell<dyn InteractiveSegment>>) {
+		let num = self.tab_num(rc);
+		let interactive = &*rc.borrow();
+		if let Some(placeholder) = cast_interactive_segment::<Placeholder>(interactive) {
+			match num {
+				Some(num) if placeholder.segments().is_empty() => {
+					let _ = write!(out, "${}", num);
+				},
+				Some(num) => {
+					let _ = write!(out, "${{{}:", num);
+					self.segments(out, placeholder.segments());
+					out.push('}');
+				},
+				None => self.segments(out, placeholder.segments())
+			}
+		} else if let Some(choice) = cast_interactive_segment::<Choice>(interactive) {
+			if let Some(num) = num {
+				let _ = write!(out, "${{{}|", num);
+				for (i, option) in choice.option_strings().iter().enumerate() {
+					if i > 0 {
+						out.push(',');
+					}
+					escape(out, option, CHOICE_SPECIAL);
+				}
+				out.push_str("|}");
+			} else if let Some(segs) = choice.choices.get(choice.selected) {
+				self.segments(out, segs);
+			}
+		} else if self.dialect == Dialect::Lsp {
+			self.text(out, &interactive.to_string());
+		} else if let Some(variable) = cast_interactive_segment::<Variable>(interactive).filter(|variable| variable.is_valid_name()) {
+			out.push_str("${");
+			out.push_str(&variable.name);
+			if let Some(transform) = &variable.transform {
+				if self.dialect == Dialect::TextMate {
+					write_textmate_transform(out, transform);
+				} else {
+					write_transform(out, transform);
+				}
+			} else if !variable.default.is_empty() {
+				out.push(':');
+				self.segments(out, &variable.default);
+			}
+			out.push('}');
+		} else if let Some(code) = cast_interactive_segment::<Code>(interactive) {
+			out.push('`');
+			escape(out, &code.code_to_run, CODE_SPECIAL);
+			out.push('`');
+		} else {
+			self.text(out, &interactive.to_string());
+		}
+	}
+}
+
+/// Transform as the `/regex/format/options` following a variable name.
+fn write_transform(out: &mut String, transform: &VariableTransform) {
+	let modifier = match transform {
+		VariableTransform::Upcase => "upcase",
+		VariableTransform::Downcase => "downcase",
+		VariableTransform::Capitalize => "capitalize",
+		VariableTransform::PascalCase => "pascalcase",
+		VariableTransform::CamelCase => "camelcase",
+		#[cfg(feature = "regex")]
+		VariableTransform::Regex { pattern, replacement, global } => {
+			out.push('/');
+			escape(out, pattern.as_str(), TRANSFORM_SPECIAL);
+			out.push('/');
+			escape(out, replacement, TRANSFORM_SPECIAL);
+			out.push('/');
+			if *global {
+				out.push('g');
+			}
+			return
+		},
+		// Visual studio code has no date formatting, the variable is written without it.
+		#[cfg(feature = "chrono")]
+		VariableTransform::DateTime(_) => return
+	};
+	let _ = write!(out, "/(.*)/${{1:/{}}}/", modifier);
+}
+
+/// Transform as the `/regexp/format/options` following a variable name in textmate.
+/// Changes of case are `\U`, `\L` and `\u` in the format rather than the `/upcase` etc of visual studio code.
+fn write_textmate_transform(out: &mut String, transform: &VariableTransform) {
+	let (regexp, format, options) = match transform {
+		VariableTransform::Upcase => (".+", "\\U$0", ""),
+		VariableTransform::Downcase => (".+", "\\L$0", ""),
+		VariableTransform::Capitalize => ("^.", "\\u$0", ""),
+		VariableTransform::PascalCase => ("[^[:alnum:]]*([[:alnum:]]+)[^[:alnum:]]*", "\\u$1", "g"),
+		VariableTransform::CamelCase => ("(?:^[^[:alnum:]]*([[:alnum:]]+)|([[:alnum:]]+))[^[:alnum:]]*", "(?1:\\L$1\\E)(?2:\\u$2)", "g"),
+		#[cfg(feature = "regex")]
+		VariableTransform::Regex { .. } => return write_transform(out, transform),
+		// Textmate has no date formatting, the variable is written without it.
+		#[cfg(feature = "chrono")]
+		VariableTransform::DateTime(_) => return
+	};
+	let _ = write!(out, "/{}/{}/{}", regexp, format, options);
+}
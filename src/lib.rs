@@ -4,84 +4,806 @@
 //! This is the responsability of another progam that uses this library so as to enable custom snippet body string syntax
 //! This also achieves the state of being unopinionated for parsing a snippet body string into segments
 pub use snippet_body::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
 use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+use std::ops::{Index, IndexMut, Range};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod builtin_resolver;
+pub use builtin_resolver::BuiltinResolver;
+mod export;
+#[cfg(feature = "serde")]
+mod serialization;
+#[cfg(feature = "serde")]
+pub use serialization::{SerializableSnippet, DeserializedSnippet};
+
+/// Reasons a segment could not be evaluated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnippetError {
+	/// No value could be found for the variable of this name.
+	VariableNotFound(String),
+	/// Shell code could not be run or exited unsuccessfully.
+	/// `code` is absent when the shell never produced an exit status.
+	ScriptFailed {
+		code: Option<i32>,
+		stderr: String
+	},
+	/// Shell code ran longer than it was allowed to and was killed.
+	Timeout,
+	/// Guard of a [`Code`] did not allow this shell code to run.
+	Blocked(String),
+	/// Variable of this name refers to variables that refer back to it.
+	InterpolationCycle(String),
+	/// [`Choice`] was given nothing to choose from.
+	EmptyChoice,
+	/// Name is not one a [`Variable`] can have, see [`Variable::is_valid_name`].
+	InvalidVariableName(String),
+	/// Index given is past the end of the choices of a [`Choice`].
+	ChoiceIndexOutOfRange {
+		index: usize,
+		len: usize
+	},
+	/// Pattern of a [`VariableTransform::Regex`] did not compile.
+	#[cfg(feature = "regex")]
+	InvalidRegex(String)
+}
+impl fmt::Display for SnippetError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SnippetError::VariableNotFound(name) => write!(f, "variable {} not found", name),
+			SnippetError::ScriptFailed { code: Some(code), stderr } => write!(f, "script exited with code {}: {}", code, stderr),
+			SnippetError::ScriptFailed { code: None, stderr } => write!(f, "script failed to run: {}", stderr),
+			SnippetError::Timeout => write!(f, "script timed out"),
+			SnippetError::InterpolationCycle(name) => write!(f, "variable {} refers back to itself", name),
+			SnippetError::Blocked(code) => write!(f, "shell code {:?} was blocked from running", code),
+			SnippetError::EmptyChoice => write!(f, "choice has no choices"),
+			SnippetError::InvalidVariableName(name) => write!(f, "{:?} is not a valid variable name", name),
+			SnippetError::ChoiceIndexOutOfRange { index, len } => write!(f, "choice {} out of range for {} choices", index, len),
+			#[cfg(feature = "regex")]
+			SnippetError::InvalidRegex(err) => write!(f, "invalid regex: {}", err)
+		}
+	}
+}
+impl std::error::Error for SnippetError {
+}
+
+thread_local! {
+	/// Segments in the midst of being displayed on this thread, to tell when one is nested within itself.
+	static DISPLAYING: RefCell<Vec<*const ()>> = const { RefCell::new(vec![]) };
+	/// Interactive segments in the midst of being compared on this thread by [`segments_eq`], those of each side.
+	static COMPARING: RefCell<(Vec<*const ()>, Vec<*const ()>)> = const { RefCell::new((vec![], vec![])) };
+	/// Interactive segments in the midst of being hashed on this thread by [`hash_segments`].
+	static HASHING: RefCell<Vec<*const ()>> = const { RefCell::new(vec![]) };
+}
+/// Displays the segments nested within the segment at `owner`.
+/// A segment nested within itself is displayed as `…` the second time round rather than recursing forever.
+fn fmt_nested(owner: *const (), segments: &[Segment], f: &mut fmt::Formatter) -> fmt::Result {
+	let nested_in_itself = DISPLAYING.with(|displaying| {
+		let mut displaying = displaying.borrow_mut();
+		if displaying.contains(&owner) {
+			true
+		} else {
+			displaying.push(owner);
+			false
+		}
+	});
+	if nested_in_itself {
+		return f.write_str("…")
+	}
+	let result = segments.iter().try_for_each(|seg| fmt::Display::fmt(seg, f));
+	DISPLAYING.with(|displaying| displaying.borrow_mut().pop());
+	result
+}
 
 /// Text typed in by user.
 /// Also serves what visual studio code and textmate describes as tabs and mirrors.
 #[derive(Debug)]
-pub struct Placeholder(
-	/// Content of placeholder.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Placeholder {
+	/// Content of placeholder shown until the user types over it.
 	/// Is vec of segments since placeholder can contain not merely just plain text but also things like other placeholders.
-	pub Vec<Segment>
-);
+	#[cfg_attr(feature = "serde", serde(with = "serialization::segments"))]
+	pub default: Vec<Segment>,
+	/// Content typed in by the user, if any.
+	#[cfg_attr(feature = "serde", serde(default, with = "serialization::optional_segments"))]
+	pub value: Option<Vec<Segment>>,
+	/// Number of the tab this is the field of, set when registered as a tab (ie by [`SnippetBuilder`]).
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub tab_num: Option<usize>
+}
+impl Placeholder {
+	pub fn new(default: Vec<Segment>) -> Placeholder {
+		Placeholder {
+			default,
+			value: None,
+			tab_num: None
+		}
+	}
+	/// Content currently shown, the user's if there is any otherwise the default.
+	pub fn segments(&self) -> &Vec<Segment> {
+		self.value.as_ref().unwrap_or(&self.default)
+	}
+	/// Mutable form of [`Placeholder::segments`].
+	pub fn segments_mut(&mut self) -> &mut Vec<Segment> {
+		self.value.as_mut().unwrap_or(&mut self.default)
+	}
+	pub fn set_value(&mut self, value: Vec<Segment>) {
+		self.value = Some(value);
+	}
+	/// Number of segments currently shown.
+	pub fn len(&self) -> usize {
+		self.segments().len()
+	}
+	pub fn is_empty(&self) -> bool {
+		self.segments().is_empty()
+	}
+	/// Discards what the user typed so the default is shown again.
+	pub fn clear(&mut self) {
+		self.value = None;
+	}
+	/// Levels of interactive segments shown, counting the placeholder itself, so one holding only text is 1.
+	/// A segment nested within itself counts once.
+	pub fn depth(&self) -> usize {
+		1 + segments_depth(self.segments(), &mut vec![self as *const Self as *const ()])
+	}
+	/// Merges each run of text segments of the default and value into one, leaving what is displayed as is.
+	/// Segments nested within are left alone, see [`SnippetExt::coalesce_text`] for those.
+	pub fn coalesce_text(&mut self) {
+		merge_text(&mut self.default);
+		if let Some(value) = &mut self.value {
+			merge_text(value);
+		}
+	}
+}
 impl fmt::Display for Placeholder {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		let Placeholder(segs) = self;
-		for seg in segs {
-			seg.fmt(f)?;
-		}
-		Ok(())
+		fmt_nested(self as *const Self as *const (), self.segments(), f)
 	}
 }
 impl InteractiveSegment for Placeholder {
 	fn get_type(&self) -> &str {
-		"placeholder"
+		SegmentType::Placeholder.as_str()
 	}
 	fn nested_printed_segments(&self) -> Option<&Vec<Segment>> {
-		Some(&self.0)
+		Some(self.segments())
+	}
+}
+impl Clone for Placeholder {
+	fn clone(&self) -> Self {
+		Placeholder {
+			default: self.default.iter().map(Segment::deep_clone).collect(),
+			value: self.value.as_ref().map(|value| value.iter().map(Segment::deep_clone).collect()),
+			tab_num: self.tab_num
+		}
 	}
 }
 impl Field for Placeholder {
 }
+/// Placeholder with the segments as its default.
+impl FromIterator<Segment> for Placeholder {
+	fn from_iter<I: IntoIterator<Item = Segment>>(segments: I) -> Placeholder {
+		Placeholder::new(segments.into_iter().collect())
+	}
+}
+/// Segments currently shown, see [`Placeholder::segments`].
+impl IntoIterator for Placeholder {
+	type Item = Segment;
+	type IntoIter = std::vec::IntoIter<Segment>;
+	fn into_iter(self) -> Self::IntoIter {
+		self.value.unwrap_or(self.default).into_iter()
+	}
+}
+/// Segment currently shown at the position, see [`Placeholder::segments`].
+impl Index<usize> for Placeholder {
+	type Output = Segment;
+	fn index(&self, index: usize) -> &Segment {
+		&self.segments()[index]
+	}
+}
+impl IndexMut<usize> for Placeholder {
+	fn index_mut(&mut self, index: usize) -> &mut Segment {
+		&mut self.segments_mut()[index]
+	}
+}
+impl<'a> IntoIterator for &'a Placeholder {
+	type Item = &'a Segment;
+	type IntoIter = std::slice::Iter<'a, Segment>;
+	fn into_iter(self) -> Self::IntoIter {
+		self.segments().iter()
+	}
+}
+/// Compares nested segments by their contents rather than by pointer.
+/// The tab number is not compared.
+impl PartialEq for Placeholder {
+	fn eq(&self, other: &Self) -> bool {
+		segments_eq(&self.default, &other.default) && match (&self.value, &other.value) {
+			(Some(value), Some(other_value)) => segments_eq(value, other_value),
+			(None, None) => true,
+			_ => false
+		}
+	}
+}
+impl Eq for Placeholder {
+}
+/// Nested segments can be changed through their RefCell, changing the hash, so a placeholder must not be changed while it is a key.
+impl Hash for Placeholder {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		hash_segments(&self.default, state);
+		self.value.as_ref().map(|value| hash_segments(value, state)).hash(state);
+	}
+}
 
 /// Choice of text selected by user from a menu of several.
+/// Best made with [`Choice::try_new`] so it is sure to have a choice selected.
 #[derive(Debug)]
-pub struct Choice(
+pub struct Choice {
 	/// Index of the chosen choice from within the outer vec of the field below.
-	pub usize,
+	pub selected: usize,
 	/// Outer vec is the choices whereas the inner vec is the segments within a given choice.
-	pub Vec<Vec<Segment>>
-);
+	pub choices: Vec<Vec<Segment>>,
+	/// Number of the tab this is the field of, see [`Placeholder::tab_num`].
+	pub tab_num: Option<usize>,
+	/// Text typed to narrow down the choices of a menu, see [`Choice::filtered_indices`].
+	/// Is not compared, hashed or serialized.
+	pub filter: String
+}
+impl Choice {
+	/// Choice with the first of `choices` selected.
+	pub fn new(choices: Vec<Vec<Segment>>) -> Choice {
+		Choice {
+			selected: 0,
+			choices,
+			tab_num: None,
+			filter: String::new()
+		}
+	}
+	/// Choice with `selected` selected, failing if there are no choices or `selected` is not one of them.
+	pub fn try_new(selected: usize, choices: Vec<Vec<Segment>>) -> Result<Choice, SnippetError> {
+		if choices.is_empty() {
+			Err(SnippetError::EmptyChoice)
+		} else if selected >= choices.len() {
+			Err(SnippetError::ChoiceIndexOutOfRange {
+				index: selected,
+				len: choices.len()
+			})
+		} else {
+			Ok(Choice {
+				selected,
+				choices,
+				tab_num: None,
+				filter: String::new()
+			})
+		}
+	}
+	pub fn push_choice(&mut self, segs: Vec<Segment>) {
+		self.choices.push(segs);
+	}
+	/// Inserts a choice at `index`, keeping the same choice selected.
+	/// Panics if `index` is past the end of the choices like `Vec::insert`.
+	pub fn insert_choice(&mut self, index: usize, segs: Vec<Segment>) {
+		self.choices.insert(index, segs);
+		if index <= self.selected && self.selected + 1 < self.choices.len() {
+			self.selected += 1;
+		}
+	}
+	/// Removes the choice at `index`, if there is one.
+	/// Keeps the same choice selected or, when the selected one is removed, whichever now sits at its index (or the last one).
+	pub fn remove_choice(&mut self, index: usize) -> Option<Vec<Segment>> {
+		if index >= self.choices.len() {
+			return None
+		}
+		let removed = self.choices.remove(index);
+		if index < self.selected {
+			self.selected -= 1;
+		}
+		self.selected = self.selected.min(self.choices.len().saturating_sub(1));
+		Some(removed)
+	}
+	/// Selects the choice at `index`, leaving the selection as is if there is no such choice.
+	pub fn select(&mut self, index: usize) -> Result<(), SnippetError> {
+		let len = self.choices.len();
+		if index >= len {
+			return Err(SnippetError::ChoiceIndexOutOfRange { index, len })
+		}
+		self.selected = index;
+		Ok(())
+	}
+	/// Selects the following choice matching the filter, going back to the first after the last.
+	pub fn next(&mut self) {
+		let indices = self.filtered_indices();
+		if let Some(&first) = indices.first() {
+			self.selected = indices.iter().copied().find(|&index| index > self.selected).unwrap_or(first);
+		}
+	}
+	/// Selects the preceding choice matching the filter, going round to the last before the first.
+	pub fn previous(&mut self) {
+		let indices = self.filtered_indices();
+		if let Some(&last) = indices.last() {
+			self.selected = indices.iter().rev().copied().find(|&index| index < self.selected).unwrap_or(last);
+		}
+	}
+	/// Indices of the choices whose displayed text contains the filter, ignoring case.
+	/// Every choice matches an empty filter.
+	pub fn filtered_indices(&self) -> Vec<usize> {
+		let filter = self.filter.to_lowercase();
+		self.option_strings().iter().enumerate().filter(|(_, option)| option.to_lowercase().contains(&filter)).map(|(index, _)| index).collect()
+	}
+	/// Every choice as it would be displayed if selected, for showing them all in a menu.
+	pub fn option_strings(&self) -> Vec<String> {
+		self.choices.iter().map(|segs| segs.iter().map(Segment::to_string).collect()).collect()
+	}
+	/// Selected choice as it is displayed, None if the selected index is out of range.
+	pub fn selected_string(&self) -> Option<String> {
+		self.choices.get(self.selected).map(|segs| segs.iter().map(Segment::to_string).collect())
+	}
+	/// Index of the first choice whose displayed text contains `needle`, ignoring case.
+	pub fn find_branch(&self, needle: &str) -> Option<usize> {
+		self.find_branch_with(needle, MatchMode::IgnoreCase)
+	}
+	/// Like `find_branch` but matching as `mode` says.
+	pub fn find_branch_with(&self, needle: &str, mode: MatchMode) -> Option<usize> {
+		let options = self.option_strings();
+		match mode {
+			MatchMode::IgnoreCase => {
+				let needle = needle.to_lowercase();
+				options.iter().position(|option| option.to_lowercase().contains(&needle))
+			},
+			MatchMode::Exact => options.iter().position(|option| option.contains(needle))
+		}
+	}
+	/// Whether any choice displays text containing `needle`, ignoring case.
+	pub fn contains(&self, needle: &str) -> bool {
+		self.find_branch(needle).is_some()
+	}
+	/// Selects the first choice found by `find_branch`, as type to filter in a menu does.
+	/// Gives the index selected, the selection is left as is when nothing matches.
+	pub fn select_matching(&mut self, needle: &str) -> Option<usize> {
+		let index = self.find_branch(needle)?;
+		self.selected = index;
+		Some(index)
+	}
+	/// Interactive segments of the selected choice and those nested within them, depth first.
+	/// These are the ones shown, so selecting another choice changes which they are.
+	pub fn active_interactive_segments(&self) -> Vec<Rc<RefCell<dyn InteractiveSegment>>> {
+		let selected = self.choices.get(self.selected).map_or(&[][..], |segs| &segs[..]);
+		WalkSegments::new(selected).filter_map(|segment| if let Segment::Interactive(interactive) = segment {
+			Some(interactive)
+		} else {
+			None
+		}).collect()
+	}
+}
+/// How [`Choice::find_branch_with`] compares text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchMode {
+	/// Upper and lower case match alike.
+	#[default]
+	IgnoreCase,
+	/// Case must match too.
+	Exact
+}
 impl fmt::Display for Choice {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		let Choice(choice, choices) = self;
-		let choice = if let Some(choice) = choices.get(*choice) {
-			choice
+		if let Some(choice) = self.choices.get(self.selected) {
+			fmt_nested(self as *const Self as *const (), choice, f)
 		} else {
-			return Ok(())
-		};
-		for seg in choice {
-			seg.fmt(f)?;
+			Ok(())
 		}
-		Ok(())
 	}
 }
 impl InteractiveSegment for Choice {
 	fn get_type(&self) -> &str {
-		"choice"
+		SegmentType::Choice.as_str()
 	}
 	fn nested_printed_segments(&self) -> Option<&Vec<Segment>> {
-		let Choice(choice, choices) = self;
-		choices.get(*choice)
+		self.choices.get(self.selected)
+	}
+}
+impl Clone for Choice {
+	fn clone(&self) -> Self {
+		Choice {
+			selected: self.selected,
+			choices: self.choices.iter().map(|choice| choice.iter().map(Segment::deep_clone).collect()).collect(),
+			tab_num: self.tab_num,
+			filter: self.filter.clone()
+		}
 	}
 }
 impl Field for Choice {
 }
+/// Compares the selected index and every choice, see [`Placeholder`] for how nested segments are compared.
+/// The tab number is not compared.
+impl PartialEq for Choice {
+	fn eq(&self, other: &Self) -> bool {
+		self.selected == other.selected && self.choices.len() == other.choices.len() && self.choices.iter().zip(&other.choices).all(|(segs, other_segs)| segments_eq(segs, other_segs))
+	}
+}
+impl Eq for Choice {
+}
+/// Must not be changed while it is a key, see [`Placeholder`].
+impl Hash for Choice {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.selected.hash(state);
+		self.choices.len().hash(state);
+		for segs in &self.choices {
+			hash_segments(segs, state);
+		}
+	}
+}
+
+/// Change applied to the value of a [`Variable`] like the `${var/regex/format/options}` transforms of visual studio code.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum VariableTransform {
+	Upcase,
+	Downcase,
+	/// First letter in upper case, the rest as is.
+	Capitalize,
+	/// Words joined with each word capitalized (`hello big world` to `HelloBigWorld`).
+	PascalCase,
+	/// Same as PascalCase except the first word is in lower case.
+	CamelCase,
+	/// Replaces matches of `pattern` with `replacement`, which may refer to captures as `$1`, `$2`, etc.
+	/// Only the first match is replaced unless `global`.
+	#[cfg(feature = "regex")]
+	Regex {
+		#[cfg_attr(feature = "serde", serde(with = "serialization::regex_pattern"))]
+		pattern: regex::Regex,
+		replacement: String,
+		global: bool
+	},
+	/// Formats a timestamp, seconds since the unix epoch or RFC 3339, with a chrono format string (ie `%Y-%m-%d`).
+	/// Date variables are formatted from the time they are of, see [`VariableResolver::timestamp`],
+	/// so the `CURRENT_*` variables of a [`BuiltinResolver`] with a fixed `now` give a fixed date.
+	/// Values that are not a timestamp are left as is.
+	#[cfg(feature = "chrono")]
+	DateTime(String)
+}
+impl PartialEq for VariableTransform {
+	fn eq(&self, other: &Self) -> bool {
+		match (self, other) {
+			#[cfg(feature = "regex")]
+			(
+				VariableTransform::Regex { pattern, replacement, global },
+				VariableTransform::Regex { pattern: other_pattern, replacement: other_replacement, global: other_global }
+			) => pattern.as_str() == other_pattern.as_str() && replacement == other_replacement && global == other_global,
+			#[cfg(feature = "chrono")]
+			(VariableTransform::DateTime(format), VariableTransform::DateTime(other_format)) => format == other_format,
+			_ => std::mem::discriminant(self) == std::mem::discriminant(other)
+		}
+	}
+}
+impl Eq for VariableTransform {
+}
+impl VariableTransform {
+	/// Regex transform from an uncompiled pattern.
+	#[cfg(feature = "regex")]
+	pub fn regex(pattern: &str, replacement: impl Into<String>, global: bool) -> Result<VariableTransform, SnippetError> {
+		Ok(VariableTransform::Regex {
+			pattern: regex::Regex::new(pattern).map_err(|err| SnippetError::InvalidRegex(err.to_string()))?,
+			replacement: replacement.into(),
+			global
+		})
+	}
+	pub fn apply(&self, value: &str) -> String {
+		match self {
+			VariableTransform::Upcase => value.to_uppercase(),
+			VariableTransform::Downcase => value.to_lowercase(),
+			VariableTransform::Capitalize => capitalize(value),
+			VariableTransform::PascalCase => words(value).map(capitalize).collect(),
+			VariableTransform::CamelCase => words(value).enumerate().map(|(i, word)| if i == 0 {
+				word.to_lowercase()
+			} else {
+				capitalize(word)
+			}).collect(),
+			#[cfg(feature = "regex")]
+			VariableTransform::Regex { pattern, replacement, global: true } => pattern.replace_all(value, replacement).into_owned(),
+			#[cfg(feature = "regex")]
+			VariableTransform::Regex { pattern, replacement, global: false } => pattern.replace(value, replacement).into_owned(),
+			#[cfg(feature = "chrono")]
+			VariableTransform::DateTime(format) => format_timestamp(value, format).unwrap_or_else(|| String::from(value))
+		}
+	}
+}
+/// Timestamp formatted, None if it is not one or the format is invalid.
+#[cfg(feature = "chrono")]
+fn format_timestamp(value: &str, format: &str) -> Option<String> {
+	use std::fmt::Write;
+	let value = value.trim();
+	let time = if let Ok(seconds) = value.parse::<i64>() {
+		chrono::DateTime::from_timestamp(seconds, 0)?
+	} else {
+		chrono::DateTime::parse_from_rfc3339(value).ok()?.with_timezone(&chrono::Utc)
+	};
+	let mut formatted = String::new();
+	write!(formatted, "{}", time.format(format)).ok()?;
+	Some(formatted)
+}
+fn capitalize(word: &str) -> String {
+	let mut chars = word.chars();
+	if let Some(first) = chars.next() {
+		first.to_uppercase().chain(chars).collect()
+	} else {
+		String::new()
+	}
+}
+fn words(value: &str) -> impl Iterator<Item = &str> {
+	value.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty())
+}
+
+/// Closure a client supplies to produce the value of a variable from its name.
+pub type GetFromClient = Box<dyn FnMut(&str) -> String>;
+
+/// Source of the values of variables, such as the state of an editor (current file, selection, clipboard).
+pub trait VariableResolver {
+	/// Value of the variable named `name`, None if there is no such variable.
+	fn resolve(&self, name: &str) -> Option<String>;
+	/// Seconds since the unix epoch of the time the variable named `name` is of, if it is a date variable.
+	/// A [`VariableTransform::DateTime`] formats this rather than the value, none by default.
+	fn timestamp(&self, _name: &str) -> Option<u64> {
+		None
+	}
+}
+
+/// Resolves variables from the environment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EnvResolver;
+impl VariableResolver for EnvResolver {
+	fn resolve(&self, name: &str) -> Option<String> {
+		std::env::var(name).ok()
+	}
+}
 
 /// Part of the snippet that is filled in by program variables (ie environment variables).
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Variable {
 	/// Name of the variable.
 	pub name: String,
 	/// Value of the variable.
 	pub value: String,
+	/// Shown in place of the value when it is empty, like `${TM_SELECTED_TEXT:default}` of visual studio code.
+	#[cfg_attr(feature = "serde", serde(default, with = "serialization::segments"))]
+	pub default: Vec<Segment>,
 	/// Where a variable comes from.
-	pub get_from_client: Option<*mut dyn FnMut(&str) -> String>
+	/// Takes precedence over `resolver`.
+	#[cfg_attr(feature = "serde", serde(skip))]
+	pub get_from_client: Option<GetFromClient>,
+	/// Where a variable comes from when there is no client closure.
+	/// When absent the variable is looked up in the environment.
+	#[cfg_attr(feature = "serde", serde(skip))]
+	pub resolver: Option<Rc<dyn VariableResolver>>,
+	/// Prepended to the name and fallbacks when looked up in the environment, so with `SNIPPET_` the variable `GREET` is `SNIPPET_GREET`.
+	/// The name is still the bare one, as given by `indentifier`.
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub env_prefix: Option<String>,
+	/// Expands `$NAME` and `${NAME}` within the resolved value (ie `$HOME/projects`), through whatever resolved the value.
+	/// References to variables that can not be found are left as is.
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub interpolate: bool,
+	/// Names tried in order when `name` resolves to nothing or an empty value (ie `TM_CURRENT_WORD` after `TM_SELECTED_TEXT`).
+	/// The first non empty value is taken.
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub fallbacks: Vec<String>,
+	/// Applied to the value once resolved.
+	pub transform: Option<VariableTransform>,
+	/// Whether the value came from resolving, see [`Variable::is_resolved`].
+	#[cfg_attr(feature = "serde", serde(default))]
+	resolved: bool,
+	/// Whether the value was set by [`Variable::set_value`] and is kept by `try_evaluate`.
+	#[cfg_attr(feature = "serde", serde(default))]
+	overridden: bool
 }
-fn get_variable_value(name: &str) -> String {
-	if let Ok(value) = std::env::var(name) {
-		value
-	} else {
-		String::new()
+/// How deep variables may refer to variables before they are taken to refer back to themselves.
+const MAX_INTERPOLATION_DEPTH: usize = 32;
+/// Resolves variables from the environment with a prefix on their names, see [`Variable::env_prefix`].
+struct PrefixedEnvResolver<'a>(&'a str);
+impl VariableResolver for PrefixedEnvResolver<'_> {
+	fn resolve(&self, name: &str) -> Option<String> {
+		std::env::var(format!("{}{}", self.0, name)).ok()
+	}
+}
+/// Resolves through the client closure of a variable, which finds every name.
+struct ClientResolver<'a>(RefCell<&'a mut GetFromClient>);
+impl VariableResolver for ClientResolver<'_> {
+	fn resolve(&self, name: &str) -> Option<String> {
+		Some((self.0.borrow_mut())(name))
+	}
+}
+/// Value with the variables it refers to expanded, None if they refer too deep.
+fn interpolate(value: &str, resolver: &dyn VariableResolver, depth: usize) -> Option<String> {
+	if depth > MAX_INTERPOLATION_DEPTH {
+		return None
+	}
+	let mut interpolated = String::new();
+	let mut rest = value;
+	while let Some(start) = rest.find('$') {
+		interpolated.push_str(&rest[..start]);
+		let after = &rest[start + 1..];
+		let (name, len) = if let Some(braced) = after.strip_prefix('{') {
+			match braced.find('}') {
+				Some(end) if is_variable_name(&braced[..end]) => (&braced[..end], end + 2),
+				_ => ("", 0)
+			}
+		} else {
+			let end = after.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(after.len());
+			if is_variable_name(&after[..end]) {
+				(&after[..end], end)
+			} else {
+				("", 0)
+			}
+		};
+		let resolved = if name.is_empty() {
+			None
+		} else {
+			resolver.resolve(name)
+		};
+		match resolved {
+			Some(nested) => interpolated.push_str(&interpolate(&nested, resolver, depth + 1)?),
+			None => interpolated.push_str(&rest[start..start + 1 + len])
+		}
+		rest = &after[len..];
+	}
+	interpolated.push_str(rest);
+	Some(interpolated)
+}
+fn is_variable_name(name: &str) -> bool {
+	let mut chars = name.chars();
+	chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+impl Variable {
+	/// Variable looked up in the environment.
+	pub fn new(name: impl Into<String>) -> Variable {
+		Variable {
+			name: name.into(),
+			value: String::new(),
+			default: vec![],
+			get_from_client: None,
+			resolver: None,
+			env_prefix: None,
+			interpolate: false,
+			fallbacks: vec![],
+			transform: None,
+			resolved: false,
+			overridden: false
+		}
+	}
+	/// Variable looked up in the environment, failing if `name` is not valid (see [`Variable::is_valid_name`]).
+	pub fn try_new(name: impl Into<String>) -> Result<Variable, SnippetError> {
+		let variable = Variable::new(name);
+		if variable.is_valid_name() {
+			Ok(variable)
+		} else {
+			Err(SnippetError::InvalidVariableName(variable.name))
+		}
+	}
+	/// Variable whose value comes from `resolver` rather than the environment.
+	pub fn with_resolver(name: impl Into<String>, resolver: impl FnMut(&str) -> String + 'static) -> Variable {
+		Variable {
+			get_from_client: Some(Box::new(resolver)),
+			..Variable::new(name)
+		}
+	}
+	/// Variable whose value comes from `resolver`, which may be shared by many variables.
+	pub fn resolved_by(name: impl Into<String>, resolver: Rc<dyn VariableResolver>) -> Variable {
+		Variable {
+			resolver: Some(resolver),
+			..Variable::new(name)
+		}
+	}
+	/// Resolves the value of the variable, failing if it can not be found.
+	/// Whatever the client closure returns, even an empty string, counts as found.
+	/// Does nothing to a value set by [`Variable::set_value`], see [`Variable::force_evaluate`].
+	pub fn try_evaluate(&mut self) -> Result<(), SnippetError> {
+		if self.overridden {
+			Ok(())
+		} else if let Some(mut get_from_client_function) = self.get_from_client.take() {
+			let result = self.try_evaluate_with(&ClientResolver(RefCell::new(&mut get_from_client_function)));
+			self.get_from_client = Some(get_from_client_function);
+			result
+		} else if let Some(resolver) = self.resolver.clone() {
+			self.try_evaluate_with(&*resolver)
+		} else if let Some(prefix) = self.env_prefix.clone() {
+			self.try_evaluate_with(&PrefixedEnvResolver(&prefix))
+		} else {
+			self.try_evaluate_with(&EnvResolver)
+		}
+	}
+	/// Resolves the value of the variable through `resolver`, whatever the variable would be resolved through otherwise.
+	/// Replaces a value set by [`Variable::set_value`].
+	pub fn try_evaluate_with(&mut self, resolver: &dyn VariableResolver) -> Result<(), SnippetError> {
+		self.resolved = false;
+		self.overridden = false;
+		if let Some(value) = self.resolve_with_fallbacks(resolver) {
+			self.value = if self.interpolate {
+				if let Some(value) = interpolate(&value, resolver, 0) {
+					value
+				} else {
+					self.value = String::new();
+					return Err(SnippetError::InterpolationCycle(self.name.clone()))
+				}
+			} else {
+				value
+			};
+			#[cfg(feature = "chrono")]
+			if matches!(self.transform, Some(VariableTransform::DateTime(_))) {
+				if let Some(seconds) = resolver.timestamp(&self.name) {
+					self.value = seconds.to_string();
+				}
+			}
+			self.apply_transform();
+			self.resolved = true;
+			Ok(())
+		} else {
+			self.value = String::new();
+			Err(SnippetError::VariableNotFound(self.name.clone()))
+		}
+	}
+	/// Whether the name is a letter or underscore followed by letters, digits and underscores (`[A-Za-z_][A-Za-z0-9_]*`).
+	/// Only such names can be written as `$NAME` and `${NAME}` in textmate and visual studio code snippets.
+	pub fn is_valid_name(&self) -> bool {
+		is_variable_name(&self.name)
+	}
+	/// Name as written in a snippet body, `${NAME}`.
+	pub fn name_with_braces(&self) -> String {
+		format!("${{{}}}", self.name)
+	}
+	/// Whether the value is what the variable last resolved to, rather than not yet or unsuccessfully resolved.
+	pub fn is_resolved(&self) -> bool {
+		self.resolved
+	}
+	/// Sets the value as though resolved to it, so that evaluating the variable (ie through `evaluate_all`) keeps it.
+	/// The value is taken as is, it is neither interpolated nor transformed.
+	pub fn set_value(&mut self, value: impl Into<String>) {
+		self.value = value.into();
+		self.resolved = true;
+		self.overridden = true;
+	}
+	/// Resolves the value of the variable like `try_evaluate`, even if it was set by [`Variable::set_value`].
+	pub fn force_evaluate(&mut self) -> Result<(), SnippetError> {
+		self.overridden = false;
+		self.try_evaluate()
+	}
+	/// First non empty value of the name or a fallback, else an empty one if any was found.
+	fn resolve_with_fallbacks(&self, resolver: &dyn VariableResolver) -> Option<String> {
+		let mut found = None;
+		for name in std::iter::once(&self.name).chain(&self.fallbacks) {
+			if let Some(value) = resolver.resolve(name) {
+				if !value.is_empty() {
+					return Some(value)
+				}
+				found = Some(value);
+			}
+		}
+		found
+	}
+	fn apply_transform(&mut self) {
+		if let Some(transform) = &self.transform {
+			self.value = transform.apply(&self.value);
+		}
+	}
+}
+/// The client closure is not cloned, so the copy is looked up through the resolver or the environment.
+impl Clone for Variable {
+	fn clone(&self) -> Self {
+		Variable {
+			name: self.name.clone(),
+			value: self.value.clone(),
+			default: self.default.iter().map(Segment::deep_clone).collect(),
+			get_from_client: None,
+			resolver: self.resolver.clone(),
+			env_prefix: self.env_prefix.clone(),
+			interpolate: self.interpolate,
+			fallbacks: self.fallbacks.clone(),
+			transform: self.transform.clone(),
+			resolved: self.resolved,
+			overridden: self.overridden
+		}
 	}
 }
 impl fmt::Debug for Variable {
@@ -89,173 +811,4459 @@ impl fmt::Debug for Variable {
 		f.debug_struct("Variable")
 		.field("name", &self.name)
 		.field("value", &self.value)
+		.field("default", &self.default)
+		.field("get_from_client", &self.get_from_client.as_ref().map(|_| "FnMut"))
+		.field("resolver", &self.resolver.as_ref().map(|_| "VariableResolver"))
+		.field("env_prefix", &self.env_prefix)
+		.field("interpolate", &self.interpolate)
+		.field("fallbacks", &self.fallbacks)
+		.field("transform", &self.transform)
+		.field("resolved", &self.resolved)
+		.field("overridden", &self.overridden)
 		.finish()
 	}
 }
+/// Compares what the variable is and shows, not where it is resolved from.
+impl PartialEq for Variable {
+	fn eq(&self, other: &Self) -> bool {
+		self.name == other.name && self.value == other.value && segments_eq(&self.default, &other.default) && self.transform == other.transform
+	}
+}
+impl Eq for Variable {
+}
+/// Hashes the name and value only.
+impl Hash for Variable {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.name.hash(state);
+		self.value.hash(state);
+	}
+}
 impl fmt::Display for Variable {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		self.value.fmt(f)
+		if let Some(default) = self.nested_printed_segments() {
+			fmt_nested(self as *const Self as *const (), default, f)
+		} else {
+			self.value.fmt(f)
+		}
 	}
 }
 impl InteractiveSegment for Variable {
 	fn get_type(&self) -> &str {
-		"variable"
+		SegmentType::Variable.as_str()
+	}
+	/// The default while the value is empty.
+	fn nested_printed_segments(&self) -> Option<&Vec<Segment>> {
+		if self.value.is_empty() && !self.default.is_empty() {
+			Some(&self.default)
+		} else {
+			None
+		}
 	}
 }
 impl Programic for Variable {
+	/// Infallible form of [`Variable::try_evaluate`].
+	/// A variable that can not be found is left empty.
 	fn evaluate(&mut self) {
-		self.value = if let Some(get_from_client_function) = self.get_from_client {
-			unsafe{
-				(*get_from_client_function)(&self.name)
-			}
-		} else {
-			get_variable_value(&self.name)
-		};
+		let _ = self.try_evaluate();
 	}
 	fn indentifier(&self) -> &String {
 		&self.name
 	}
 }
 
+/// Program that [`Code`] is run by.
+/// Shells are run through run_script, other programs are given the code as a script file.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ScriptRunner {
+	/// Default shell of the system.
+	#[default]
+	System,
+	Sh,
+	Bash,
+	Python,
+	Node,
+	/// Any other program, which is given the code as a script file.
+	Custom(String)
+}
+impl ScriptRunner {
+	fn program(&self) -> Option<&str> {
+		match self {
+			ScriptRunner::System => None,
+			ScriptRunner::Sh => Some("sh"),
+			ScriptRunner::Bash => Some("bash"),
+			ScriptRunner::Python => Some("python3"),
+			ScriptRunner::Node => Some("node"),
+			ScriptRunner::Custom(program) => Some(program)
+		}
+	}
+}
+
+/// What is trimmed off the output of [`Code`] once it has run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TrimMode {
+	/// Output is kept as is.
+	#[default]
+	None,
+	/// One trailing line ending is removed, such as the one `echo` ends with.
+	TrailingNewline,
+	/// Whitespace is removed from both ends.
+	Both
+}
+impl TrimMode {
+	/// Trims `output` in place.
+	pub fn apply(&self, output: &mut String) {
+		match self {
+			TrimMode::None => {},
+			TrimMode::TrailingNewline => if output.ends_with('\n') {
+				output.pop();
+				if output.ends_with('\r') {
+					output.pop();
+				}
+			},
+			TrimMode::Both => *output = String::from(output.trim())
+		}
+	}
+}
+
 /// [Shell Code](https://macromates.com/manual/en/snippets#interpolated_shell_code) to run.
 /// Output will be the string show/expanded within the snippet
-#[derive(Debug)]
+/// Unlike the other segments this is Send and Sync, see [`CodeJobs`] for running it on another thread.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Code {
 	pub code_to_run: String,
-	pub output: String
+	pub output: String,
+	/// Diagnostics written by the last run.
+	/// Unlike output this is not shown within the snippet.
+	pub stderr: String,
+	/// Exit code of the last run, if the shell produced one.
+	pub last_exit_code: Option<i32>,
+	/// What runs the code.
+	pub runner: ScriptRunner,
+	/// How long the code may run before it is killed.
+	/// Runs for as long as it takes when absent.
+	pub timeout: Option<Duration>,
+	/// Extra environment variables the code is run with (ie `TM_SELECTED_TEXT`).
+	pub env: Vec<(String, String)>,
+	/// Arguments passed to the code.
+	pub args: Vec<String>,
+	/// Directory the code is run in (ie that of the edited file), the current directory of the process when absent.
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub working_dir: Option<PathBuf>,
+	/// Trimmed off the output once run.
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub trim_output: TrimMode,
+	/// Keeps the output of the last successful run instead of running the same code again.
+	pub cache: bool,
+	/// Hash of the code that produced the cached output.
+	#[cfg_attr(feature = "serde", serde(skip))]
+	cached_code: Option<u64>,
+	/// Whether the output came from running, see [`Code::is_evaluated`].
+	#[cfg_attr(feature = "serde", serde(default))]
+	evaluated: bool,
+	/// Vets the code before each run, see [`Code::set_guard`].
+	#[cfg_attr(feature = "serde", serde(skip))]
+	guard: Option<CodeGuard>,
+	/// Runs the code in place of run_script, see [`Code::set_executor`].
+	#[cfg_attr(feature = "serde", serde(skip))]
+	executor: Option<SharedExecutor>
 }
-impl fmt::Display for Code {
+/// What running shell code gave, see [`CodeExecutor`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CodeResult {
+	/// Zero for success, anything else fails the evaluation.
+	pub exit_code: i32,
+	/// Shown within the snippet.
+	pub stdout: String,
+	pub stderr: String
+}
+/// Runs shell code where run_script can not, ie a web assembly host proxying to javascript.
+pub trait CodeExecutor: Send + Sync {
+	/// Runs `code` with the extra environment variables `env`, failing if it could not be run at all.
+	fn run(&self, code: &str, env: &[(String, String)]) -> Result<CodeResult, SnippetError>;
+}
+/// Executor given to [`Code::set_executor`], shared by copies of the code.
+#[derive(Clone)]
+struct SharedExecutor(std::sync::Arc<dyn CodeExecutor>);
+impl fmt::Debug for SharedExecutor {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		self.output.fmt(f)
+		f.write_str("CodeExecutor")
 	}
 }
-impl InteractiveSegment for Code {
-	fn get_type(&self) -> &str {
-		"code"
+/// Callback given to [`Code::set_guard`], shared by copies of the code.
+#[derive(Clone)]
+struct CodeGuard(std::sync::Arc<dyn Fn(&str) -> bool + Send + Sync>);
+impl fmt::Debug for CodeGuard {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("Fn")
 	}
 }
-impl Programic for Code {
-	fn evaluate(&mut self) {
-		let options = run_script::ScriptOptions::new();
-		let args = vec![];
-		let (_, output, _) = run_script::run(&self.code_to_run, &args, &options).unwrap();
-		self.output = output;
+impl Code {
+	pub fn new(code_to_run: impl Into<String>) -> Code {
+		Code {
+			code_to_run: code_to_run.into(),
+			output: String::new(),
+			stderr: String::new(),
+			last_exit_code: None,
+			runner: ScriptRunner::default(),
+			timeout: None,
+			env: vec![],
+			args: vec![],
+			working_dir: None,
+			trim_output: TrimMode::None,
+			cache: false,
+			cached_code: None,
+			evaluated: false,
+			guard: None,
+			executor: None
+		}
 	}
-	fn indentifier(&self) -> &String {
+	/// Whether the output is that of the last run, rather than not yet or unsuccessfully run.
+	pub fn is_evaluated(&self) -> bool {
+		self.evaluated
+	}
+	/// What evaluating would run, without running it.
+	pub fn dry_run(&self) -> &str {
 		&self.code_to_run
 	}
-}
-
-#[cfg(test)]
-mod tests {
-	use super::*;
-	use std::rc::Rc;
-	use std::cell::RefCell;
-	#[test]
-	fn trait_object_casting() {
-		let mut snippet = Snippet {
-			body: vec![],
-			tabs: vec![],
-			program_filled_text: vec![],
-			references: vec![]
-		};
-		let placeholder = Placeholder(vec![
-			Segment::Text(String::from("hello")),
-			Segment::Text(String::from("there!"))
-		]);
-		let placeholder_rc = Rc::new(RefCell::new(placeholder));
-		let placeholder_clone = Rc::clone(&placeholder_rc);
-		let tab = Tab {
-			num: 1,
-			field: placeholder_clone
+	/// Has `guard` vet the code before every run, ie to block `rm -rf` or ask the user first.
+	/// Code that the guard returns false for is not run and fails with [`SnippetError::Blocked`], leaving output empty.
+	pub fn set_guard(&mut self, guard: impl Fn(&str) -> bool + Send + Sync + 'static) {
+		self.guard = Some(CodeGuard(std::sync::Arc::new(guard)));
+	}
+	/// Removes the guard given to `set_guard`.
+	pub fn clear_guard(&mut self) {
+		self.guard = None;
+	}
+	/// Has `executor` run the code rather than run_script.
+	/// The executor is given the code and `env` only, so `runner`, `args`, `working_dir` and `timeout` are up to it.
+	pub fn set_executor(&mut self, executor: impl CodeExecutor + 'static) {
+		self.executor = Some(SharedExecutor(std::sync::Arc::new(executor)));
+	}
+	/// Goes back to running the code through run_script.
+	pub fn clear_executor(&mut self) {
+		self.executor = None;
+	}
+	/// Exit code, output and diagnostics of running the code through the executor, if there is one.
+	fn execute(&self) -> Option<Result<(i32, String, String), SnippetError>> {
+		let SharedExecutor(executor) = self.executor.as_ref()?;
+		Some(executor.run(&self.code_to_run, &self.env).map(|CodeResult { exit_code, stdout, stderr }| (exit_code, stdout, stderr)))
+	}
+	/// Keeps what a run gave, failing if it exited with a non zero code.
+	fn finish_run(&mut self, code: i32, output: String, stderr: String) -> Result<(), SnippetError> {
+		self.last_exit_code = Some(code);
+		self.stderr = stderr;
+		if code != 0 {
+			return Err(SnippetError::ScriptFailed {
+				code: Some(code),
+				stderr: self.stderr.clone()
+			})
+		}
+		self.output = output;
+		self.trim_output.apply(&mut self.output);
+		self.cached_code = Some(self.code_hash());
+		self.evaluated = true;
+		Ok(())
+	}
+	/// Clears what the last run left, before running again.
+	fn reset_run(&mut self) {
+		self.cached_code = None;
+		self.evaluated = false;
+		self.output = String::new();
+		self.stderr = String::new();
+		self.last_exit_code = None;
+	}
+	/// Fails, clearing what the last run left, if the guard does not allow the code to run.
+	fn check_guard(&mut self) -> Result<(), SnippetError> {
+		if let Some(CodeGuard(guard)) = &self.guard {
+			if !guard(&self.code_to_run) {
+				self.reset_run();
+				return Err(SnippetError::Blocked(self.code_to_run.clone()))
+			}
+		}
+		Ok(())
+	}
+	/// Makes the next evaluation run the code even if it is cached.
+	pub fn invalidate_cache(&mut self) {
+		self.cached_code = None;
+	}
+	fn code_hash(&self) -> u64 {
+		let mut hasher = std::collections::hash_map::DefaultHasher::new();
+		self.code_to_run.hash(&mut hasher);
+		hasher.finish()
+	}
+	fn script_options(&self) -> run_script::ScriptOptions {
+		let mut options = run_script::ScriptOptions::new();
+		options.runner = self.runner.program().map(String::from);
+		if !self.env.is_empty() {
+			options.env_vars = Some(self.env.iter().cloned().collect());
+		}
+		options.working_directory = self.working_dir.clone();
+		options
+	}
+	/// Starts the code with its output piped, through run_script for the shells and otherwise as a script file given to the runner.
+	/// run_script starts every script with a `cd` line, which only a shell understands.
+	fn spawn(&self) -> Result<(std::process::Child, Option<ScriptFile>), SnippetError> {
+		let Some(program) = self.runner.program().filter(|_| !matches!(self.runner, ScriptRunner::Sh | ScriptRunner::Bash)) else {
+			let child = run_script::spawn(&self.code_to_run, &self.args, &self.script_options()).map_err(script_error)?;
+			return Ok((child, None))
 		};
-		let mut code = Code {
-			code_to_run: String::from("greet=hi echo no"),
-			output: String::new()
+		let script = ScriptFile::write(&self.code_to_run)?;
+		let mut command = std::process::Command::new(program);
+		command.arg(&script.0)
+		.args(&self.args)
+		.envs(self.env.iter().cloned())
+		.stdin(std::process::Stdio::null())
+		.stdout(std::process::Stdio::piped())
+		.stderr(std::process::Stdio::piped());
+		if let Some(working_dir) = &self.working_dir {
+			command.current_dir(working_dir);
+		}
+		Ok((command.spawn().map_err(script_error)?, Some(script)))
+	}
+	/// Runs the code, failing if the shell can not be run or exits with a non zero code.
+	/// Output is left empty on failure.
+	/// Does nothing when `cache` is set and the code is unchanged since it last ran successfully.
+	pub fn try_evaluate(&mut self) -> Result<(), SnippetError> {
+		self.check_guard()?;
+		let hash = self.code_hash();
+		if self.cache && self.cached_code == Some(hash) {
+			return Ok(())
+		}
+		self.reset_run();
+		let (code, output, stderr) = if let Some(result) = self.execute() {
+			result?
+		} else {
+			let (child, _script) = self.spawn()?;
+			if let Some(timeout) = self.timeout {
+				wait_with_timeout(child, timeout)?
+			} else {
+				let output = child.wait_with_output().map_err(script_error)?;
+				(output.status.code().unwrap_or(-1), String::from_utf8_lossy(&output.stdout).into_owned(), String::from_utf8_lossy(&output.stderr).into_owned())
+			}
 		};
-		code.evaluate();
-		let code_rc = Rc::new(RefCell::new(code));
-		let code_clone = Rc::clone(&code_rc);
-		snippet.program_filled_text.push(code_clone);
-		let placeholder_weak = Rc::downgrade(&placeholder_rc);
-		let mut placeholder = Placeholder(vec![
-			Segment::Interactive(placeholder_rc)
-		]);
+		self.finish_run(code, output, stderr)
+	}
+	/// Runs the code like [`Code::try_evaluate`] but calls `on_line` with each line of output as soon as the code writes it.
+	/// Lines are given without their line ending, the whole output is kept in `output` once the code is done.
+	/// Always runs, even if cached, and does not honour `timeout`.
+	/// An executor given to `set_executor` gives all of its output at once, so the lines are only passed on once it is done.
+	pub fn evaluate_streaming(&mut self, mut on_line: impl FnMut(&str)) -> Result<(), SnippetError> {
+		self.check_guard()?;
+		self.reset_run();
+		if let Some(result) = self.execute() {
+			let (code, output, stderr) = result?;
+			output.lines().for_each(&mut on_line);
+			return self.finish_run(code, output, stderr)
+		}
+		let (mut child, _script) = self.spawn()?;
+		let stderr = read_in_background(child.stderr.take());
+		let mut output = String::new();
+		if let Some(stdout) = child.stdout.take() {
+			let mut stdout = BufReader::new(stdout);
+			let mut line = String::new();
+			loop {
+				match stdout.read_line(&mut line) {
+					Ok(0) => break,
+					Ok(_) => {
+						on_line(line.trim_end_matches(['\n', '\r']));
+						output.push_str(&line);
+						line.clear();
+					},
+					Err(err) => {
+						let _ = child.kill();
+						let _ = child.wait();
+						return Err(script_error(err))
+					}
+				}
+			}
+		}
+		let status = child.wait().map_err(script_error)?;
+		self.finish_run(status.code().unwrap_or(-1), output, stderr.join().unwrap_or_default())
+	}
+}
+/// Tells apart the script files of concurrent runs.
+static SCRIPT_COUNT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+/// Temporary file holding the code for a runner that is not a shell, removed once dropped.
+struct ScriptFile(PathBuf);
+impl ScriptFile {
+	fn write(code: &str) -> Result<ScriptFile, SnippetError> {
+		let count = SCRIPT_COUNT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+		let script = ScriptFile(std::env::temp_dir().join(format!("snippet-segment-types-{}-{}", std::process::id(), count)));
+		std::fs::write(&script.0, code).map_err(script_error)?;
+		Ok(script)
+	}
+}
+impl Drop for ScriptFile {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_file(&self.0);
+	}
+}
+#[cfg(feature = "tokio")]
+impl Code {
+	/// Asynchronous form of [`Code::try_evaluate`] that awaits the code rather than blocking the thread.
+	/// The code is written to a temporary script file that is given to the runner, the system shell being `sh` (`cmd` on windows).
+	/// Code still running once the timeout is up is killed.
+	/// An executor given to `set_executor` is run on the thread as is.
+	pub async fn evaluate_async(&mut self) -> Result<(), SnippetError> {
+		self.check_guard()?;
+		let hash = self.code_hash();
+		if self.cache && self.cached_code == Some(hash) {
+			return Ok(())
+		}
+		self.reset_run();
+		if let Some(result) = self.execute() {
+			let (code, output, stderr) = result?;
+			return self.finish_run(code, output, stderr)
+		}
+		let count = SCRIPT_COUNT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+		let extension = if cfg!(windows) && self.runner == ScriptRunner::System { ".cmd" } else { "" };
+		let script = std::env::temp_dir().join(format!("snippet-segment-types-{}-{}{}", std::process::id(), count, extension));
+		tokio::fs::write(&script, &self.code_to_run).await.map_err(script_error)?;
+		let mut command = match self.runner.program() {
+			Some(program) => tokio::process::Command::new(program),
+			None if cfg!(windows) => {
+				let mut command = tokio::process::Command::new("cmd");
+				command.arg("/C");
+				command
+			},
+			None => tokio::process::Command::new("sh")
+		};
+		command.arg(&script)
+		.args(&self.args)
+		.envs(self.env.iter().cloned())
+		.stdin(std::process::Stdio::null())
+		.kill_on_drop(true);
+		if let Some(working_dir) = &self.working_dir {
+			command.current_dir(working_dir);
+		}
+		let output = command.output();
+		let result = if let Some(timeout) = self.timeout {
+			tokio::time::timeout(timeout, output).await.map_err(|_| SnippetError::Timeout)
+		} else {
+			Ok(output.await)
+		};
+		let _ = tokio::fs::remove_file(&script).await;
+		let output = result?.map_err(script_error)?;
+		self.finish_run(output.status.code().unwrap_or(-1), String::from_utf8_lossy(&output.stdout).into_owned(), String::from_utf8_lossy(&output.stderr).into_owned())
+	}
+}
+fn script_error(err: impl fmt::Display) -> SnippetError {
+	SnippetError::ScriptFailed {
+		code: None,
+		stderr: err.to_string()
+	}
+}
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<String> {
+	thread::spawn(move || {
+		let mut read = String::new();
+		if let Some(mut pipe) = pipe {
+			let _ = pipe.read_to_string(&mut read);
+		}
+		read
+	})
+}
+/// Waits for the output of `child` but kills it once `timeout` has passed.
+/// Pipes are read on their own threads so a chatty script can not block on a full pipe.
+fn wait_with_timeout(mut child: std::process::Child, timeout: Duration) -> Result<(i32, String, String), SnippetError> {
+	let output = read_in_background(child.stdout.take());
+	let stderr = read_in_background(child.stderr.take());
+	let deadline = Instant::now() + timeout;
+	let status = loop {
+		match child.try_wait().map_err(script_error)? {
+			Some(status) => break status,
+			None if Instant::now() < deadline => thread::sleep(Duration::from_millis(5)),
+			None => {
+				let _ = child.kill();
+				let _ = child.wait();
+				// Processes started by the shell may still hold the pipes open so the readers are left to finish on their own.
+				return Err(SnippetError::Timeout)
+			}
+		}
+	};
+	let output = output.join().unwrap_or_default();
+	let stderr = stderr.join().unwrap_or_default();
+	Ok((status.code().unwrap_or(-1), output, stderr))
+}
+impl fmt::Display for Code {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.output.fmt(f)
+	}
+}
+impl InteractiveSegment for Code {
+	fn get_type(&self) -> &str {
+		SegmentType::Code.as_str()
+	}
+}
+impl Programic for Code {
+	/// Infallible form of [`Code::try_evaluate`].
+	fn evaluate(&mut self) {
+		let _ = self.try_evaluate();
+	}
+	fn indentifier(&self) -> &String {
+		&self.code_to_run
+	}
+}
+
+/// Compares the code and what it output, not how it is run.
+impl PartialEq for Code {
+	fn eq(&self, other: &Self) -> bool {
+		self.code_to_run == other.code_to_run && self.output == other.output
+	}
+}
+impl Eq for Code {
+}
+impl Hash for Code {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.code_to_run.hash(state);
+		self.output.hash(state);
+	}
+}
+
+/// Whether [`SnippetExt::evaluate_all_with`] and the like run shell code.
+/// Shell code is skipped unless `Run` is asked for, so the user can first be shown what would run (see [`Code::dry_run`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CodePolicy {
+	/// Runs shell code, only for snippets from trusted sources.
+	Run,
+	#[default]
+	Skip
+}
+
+/// Copies of the shell code of a snippet to be evaluated on another thread, see [`SnippetExt::code_jobs`].
+/// Snippets themselves are held together by Rc so can not leave the thread that made them.
+#[derive(Debug, Clone, Default)]
+pub struct CodeJobs(pub Vec<Code>);
+impl CodeJobs {
+	/// Evaluates every job one after another, blocking until the last has finished or timed out.
+	/// Carries on past failures and returns all of them.
+	pub fn evaluate(&mut self) -> Result<(), Vec<SnippetError>> {
+		let errors: Vec<SnippetError> = self.0.iter_mut().filter_map(|code| code.try_evaluate().err()).collect();
+		if errors.is_empty() {
+			Ok(())
+		} else {
+			Err(errors)
+		}
+	}
+}
+
+/// Named reference to the value of a programic segment (a variable or code) so the one evaluated value can be shown in several spots.
+/// Stands in for a computed variant of [`Reference`], which only holds text and can not be added to from this library.
+/// Evaluating the source is left to whatever evaluates it, ie being in `program_filled_text`.
+/// Can not be serialized since the source may be anywhere.
+#[derive(Debug, Clone)]
+pub struct ComputedReference {
+	/// Identifier the reference is known by.
+	pub name: String,
+	/// Segment whose value is shown.
+	pub source: Rc<RefCell<dyn Programic>>
+}
+impl ComputedReference {
+	pub fn new(name: impl Into<String>, source: Rc<RefCell<dyn Programic>>) -> ComputedReference {
+		ComputedReference {
+			name: name.into(),
+			source
+		}
+	}
+	/// Text of the source as last evaluated.
+	pub fn resolve(&self) -> String {
+		self.source.borrow().to_string()
+	}
+}
+impl fmt::Display for ComputedReference {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.source.borrow().fmt(f)
+	}
+}
+impl InteractiveSegment for ComputedReference {
+	fn get_type(&self) -> &str {
+		SegmentType::ComputedReference.as_str()
+	}
+}
+
+/// Immutable counterpart of `cast_mut_programic`.
+pub fn cast_programic<T: Programic + 'static>(obj: &dyn Programic) -> Option<&T> {
+	cast_interactive_segment::<T>(obj)
+}
+
+/// Mutable counterpart of `cast_interactive_segment`.
+/// Checks the type with `cast_interactive_segment`, so it relies on no more of snippet_body than the immutable casts.
+pub fn cast_mut_interactive_segment<T: InteractiveSegment + 'static>(obj: &mut dyn InteractiveSegment) -> Option<&mut T> {
+	if cast_interactive_segment::<T>(obj).is_some() {
+		// SAFETY: the cast above found the segment behind obj to be a T, so the data pointer of obj points at a T.
+		// The exclusive borrow of obj is handed on to the result, which has the same lifetime.
+		Some(unsafe { &mut *(obj as *mut dyn InteractiveSegment as *mut T) })
+	} else {
+		None
+	}
+}
+
+/// Mutable counterpart of `cast_field`.
+pub fn cast_mut_field<T: Field + 'static>(obj: &mut dyn Field) -> Option<&mut T> {
+	cast_mut_interactive_segment::<T>(obj)
+}
+
+/// Concrete pointer behind an interactive segment so it can be shared as another trait object (ie as a Field of a Tab).
+/// Gives the pointer back if the segment is not a T.
+/// Panics if the segment is mutably borrowed at the moment, like `RefCell::borrow`, since its type can not be told until then.
+pub fn downcast_rc<T: InteractiveSegment + 'static>(rc: Rc<RefCell<dyn InteractiveSegment>>) -> Result<Rc<RefCell<T>>, Rc<RefCell<dyn InteractiveSegment>>> {
+	let is_t = cast_interactive_segment::<T>(&*rc.borrow()).is_some();
+	if is_t {
+		// SAFETY: an Rc<RefCell<dyn InteractiveSegment>> can only be made by unsizing the Rc<RefCell<U>> of some sized U,
+		// which the cast above found to be T. Dropping the vtable keeps the address, so from_raw is given back
+		// what into_raw gave for an allocation laid out as RefCell<T>, as `Rc::downcast` does for `Rc<dyn Any>`.
+		Ok(unsafe { Rc::from_raw(Rc::into_raw(rc) as *const RefCell<T>) })
+	} else {
+		Err(rc)
+	}
+}
+/// The segment as a Field if it is one of the fields of this library.
+fn as_field(rc: Rc<RefCell<dyn InteractiveSegment>>) -> Option<Rc<RefCell<dyn Field>>> {
+	match downcast_rc::<Placeholder>(rc) {
+		Ok(placeholder) => Some(placeholder),
+		Err(rc) => downcast_rc::<Choice>(rc).ok().map(|choice| choice as Rc<RefCell<dyn Field>>)
+	}
+}
+/// The segment as a Programic if it is one of the programic segments of this library.
+fn as_programic(rc: Rc<RefCell<dyn InteractiveSegment>>) -> Option<Rc<RefCell<dyn Programic>>> {
+	match downcast_rc::<Variable>(rc) {
+		Ok(variable) => Some(variable),
+		Err(rc) => downcast_rc::<Code>(rc).ok().map(|code| code as Rc<RefCell<dyn Programic>>)
+	}
+}
+
+/// Type of an interactive segment, the matchable form of `get_type`.
+/// Segment types added to this library later get a variant of their own, so matches need a wildcard arm.
+/// Implementors from outside of this library are `Other` and can be downcast with `cast_interactive_segment` like those of this library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SegmentType<'a> {
+	Placeholder,
+	Choice,
+	Variable,
+	Code,
+	ComputedReference,
+	/// Implementor from outside of this library, by its `get_type`.
+	Other(&'a str)
+}
+impl<'a> SegmentType<'a> {
+	/// What `get_type` returns for segments of this type.
+	pub fn as_str(&self) -> &'a str {
+		match self {
+			SegmentType::Placeholder => "placeholder",
+			SegmentType::Choice => "choice",
+			SegmentType::Variable => "variable",
+			SegmentType::Code => "code",
+			SegmentType::ComputedReference => "computed_reference",
+			SegmentType::Other(segment_type) => segment_type
+		}
+	}
+}
+
+/// Additions to InteractiveSegment trait objects.
+pub trait InteractiveSegmentExt {
+	/// Type of the segment.
+	/// Found by downcasting so an implementor from outside of this library is always `Other`, even if its `get_type` clashes with one of this library.
+	fn kind(&self) -> SegmentType<'_>;
+	/// Deep copy of the segment behind a new pointer.
+	/// A ComputedReference is copied with its source still shared, as it refers to a segment found elsewhere.
+	/// None for implementors outside of this library since they can not be copied.
+	fn clone_box(&self) -> Option<Rc<RefCell<dyn InteractiveSegment>>>;
+	/// Mutable counterpart of `nested_printed_segments`.
+	/// The contents of a placeholder or the selected branch of a choice.
+	fn nested_segments_mut(&mut self) -> Option<&mut Vec<Segment>>;
+	/// Evaluates the segment, if it is a variable or code, and every variable and code shown within it.
+	/// Stands in for a default method of InteractiveSegment, which can not be added to from this library.
+	/// Shell code is only run if `policy` allows it.
+	/// Carries on past failures and returns all of them.
+	fn try_evaluate(&mut self, policy: CodePolicy) -> Result<(), Vec<SnippetError>>;
+}
+impl InteractiveSegmentExt for dyn InteractiveSegment {
+	fn kind(&self) -> SegmentType<'_> {
+		if cast_interactive_segment::<Placeholder>(self).is_some() {
+			SegmentType::Placeholder
+		} else if cast_interactive_segment::<Choice>(self).is_some() {
+			SegmentType::Choice
+		} else if cast_interactive_segment::<Variable>(self).is_some() {
+			SegmentType::Variable
+		} else if cast_interactive_segment::<Code>(self).is_some() {
+			SegmentType::Code
+		} else if cast_interactive_segment::<ComputedReference>(self).is_some() {
+			SegmentType::ComputedReference
+		} else {
+			SegmentType::Other(self.get_type())
+		}
+	}
+	fn nested_segments_mut(&mut self) -> Option<&mut Vec<Segment>> {
+		if cast_interactive_segment::<Placeholder>(self).is_some() {
+			cast_mut_interactive_segment::<Placeholder>(self).map(Placeholder::segments_mut)
+		} else {
+			let Choice { selected, choices, .. } = cast_mut_interactive_segment::<Choice>(self)?;
+			choices.get_mut(*selected)
+		}
+	}
+	fn try_evaluate(&mut self, policy: CodePolicy) -> Result<(), Vec<SnippetError>> {
+		let mut errors = vec![];
+		evaluate_nested(self, policy, &mut vec![], &mut errors);
+		if errors.is_empty() {
+			Ok(())
+		} else {
+			Err(errors)
+		}
+	}
+	fn clone_box(&self) -> Option<Rc<RefCell<dyn InteractiveSegment>>> {
+		if let Some(placeholder) = cast_interactive_segment::<Placeholder>(self) {
+			Some(Rc::new(RefCell::new(placeholder.clone())))
+		} else if let Some(choice) = cast_interactive_segment::<Choice>(self) {
+			Some(Rc::new(RefCell::new(choice.clone())))
+		} else if let Some(variable) = cast_interactive_segment::<Variable>(self) {
+			Some(Rc::new(RefCell::new(variable.clone())))
+		} else if let Some(reference) = cast_interactive_segment::<ComputedReference>(self) {
+			Some(Rc::new(RefCell::new(reference.clone())))
+		} else {
+			cast_interactive_segment::<Code>(self).map(|code| Rc::new(RefCell::new(code.clone())) as Rc<RefCell<dyn InteractiveSegment>>)
+		}
+	}
+}
+/// Evaluates `interactive` and then those shown within it, skipping the ones already `visited`.
+fn evaluate_nested(interactive: &mut (dyn InteractiveSegment + 'static), policy: CodePolicy, visited: &mut Vec<*const ()>, errors: &mut Vec<SnippetError>) {
+	if let Some(Err(err)) = try_evaluate_segment(interactive, policy) {
+		errors.push(err);
+	}
+	if let Some(nested) = interactive.nested_segments_mut() {
+		for segment in nested.iter() {
+			if let Segment::Interactive(nested) = segment {
+				let pointer = Rc::as_ptr(nested) as *const ();
+				if visited.contains(&pointer) {
+					continue
+				}
+				visited.push(pointer);
+				// Fails for a segment nested within itself, which is already being evaluated.
+				if let Ok(mut nested) = nested.try_borrow_mut() {
+					evaluate_nested(&mut *nested, policy, visited, errors);
+				}
+			}
+		}
+	}
+}
+/// Deepest level of interactive segments shown through `segments`, 0 for only text.
+/// `ancestors` are the segments the walk is within, by the pointers that `fmt_nested` is given.
+fn segments_depth(segments: &[Segment], ancestors: &mut Vec<*const ()>) -> usize {
+	segments.iter().map(|segment| if let Segment::Interactive(interactive) = segment {
+		let pointer = interactive.as_ptr() as *const ();
+		if ancestors.contains(&pointer) {
+			return 1
+		}
+		ancestors.push(pointer);
+		let depth = 1 + interactive.borrow().nested_printed_segments().map_or(0, |nested| segments_depth(nested, ancestors));
+		ancestors.pop();
+		depth
+	} else {
+		0
+	}).max().unwrap_or(0)
+}
+/// Renders `segments`, writing `…` in place of interactive segments more than `depth_left` levels deep.
+fn render_limited(out: &mut String, segments: &[Segment], depth_left: usize) {
+	for segment in segments {
+		if let Segment::Interactive(interactive) = segment {
+			if depth_left == 0 {
+				out.push('…');
+				continue
+			}
+			let interactive = interactive.borrow();
+			if let Some(nested) = interactive.nested_printed_segments() {
+				render_limited(out, nested, depth_left - 1);
+			} else {
+				out.push_str(&interactive.to_string());
+			}
+		} else {
+			out.push_str(&segment.to_string());
+		}
+	}
+}
+
+/// Additions to Field trait objects.
+pub trait FieldExt {
+	/// Number of the tab the field is registered with, if known.
+	/// Always None for implementors outside of this library.
+	fn tab_num(&self) -> Option<usize>;
+	/// Sets the number returned by `tab_num`, does nothing for implementors outside of this library.
+	fn set_tab_num(&mut self, num: Option<usize>);
+}
+impl FieldExt for dyn Field {
+	fn tab_num(&self) -> Option<usize> {
+		if let Some(placeholder) = cast_field::<Placeholder>(self) {
+			placeholder.tab_num
+		} else {
+			cast_field::<Choice>(self).and_then(|choice| choice.tab_num)
+		}
+	}
+	fn set_tab_num(&mut self, num: Option<usize>) {
+		if let Some(placeholder) = cast_mut_field::<Placeholder>(self) {
+			placeholder.tab_num = num;
+		} else if let Some(choice) = cast_mut_field::<Choice>(self) {
+			choice.tab_num = num;
+		}
+	}
+}
+
+/// Additions to Segment.
+pub trait SegmentExt {
+	/// Plain text segment, short for `Segment::Text(String::from(text))`.
+	fn text(text: impl Into<String>) -> Self where Self: Sized;
+	/// Copy of the segment that shares no pointers with the original.
+	/// Interactive segments that can not be copied (see [`InteractiveSegmentExt::clone_box`]) stay shared.
+	fn deep_clone(&self) -> Segment;
+	/// Whether the segment renders to nothing.
+	fn is_empty(&self) -> bool;
+	/// Number of chars the segment renders to.
+	fn rendered_len(&self) -> usize;
+	/// Text of the segment if it is plain text.
+	fn as_text(&self) -> Option<&str>;
+	/// Pointer to the interactive segment if it is one.
+	fn as_interactive(&self) -> Option<&Rc<RefCell<dyn InteractiveSegment>>>;
+	/// Pointer to the reference if it is one.
+	fn as_reference(&self) -> Option<&Rc<RefCell<Reference>>>;
+	fn is_text(&self) -> bool {
+		self.as_text().is_some()
+	}
+	fn is_interactive(&self) -> bool {
+		self.as_interactive().is_some()
+	}
+	fn is_reference(&self) -> bool {
+		self.as_reference().is_some()
+	}
+	/// Whether both are the same interactive segment or reference rather than merely equal, ie a mirror and the tab it mirrors.
+	/// Text is never the same as anything.
+	fn refers_same(&self, other: &Segment) -> bool {
+		if let (Some(interactive), Some(other)) = (self.as_interactive(), other.as_interactive()) {
+			Rc::as_ptr(interactive) as *const () == Rc::as_ptr(other) as *const ()
+		} else if let (Some(reference), Some(other)) = (self.as_reference(), other.as_reference()) {
+			Rc::ptr_eq(reference, other)
+		} else {
+			false
+		}
+	}
+}
+/// Whether an interactive segment renders to nothing, see [`SegmentExt::is_empty`].
+fn interactive_is_empty(interactive: &dyn InteractiveSegment) -> bool {
+	interactive_is_empty_within(interactive, &mut vec![])
+}
+/// Whether an interactive segment nested within `ancestors` renders to nothing.
+/// A segment nested within itself is shown as `…` (see `fmt_nested`) so is not empty.
+fn interactive_is_empty_within(interactive: &dyn InteractiveSegment, ancestors: &mut Vec<*const ()>) -> bool {
+	let pointer = interactive as *const dyn InteractiveSegment as *const ();
+	if ancestors.contains(&pointer) {
+		return false
+	}
+	if let Some(nested) = interactive.nested_printed_segments() {
+		ancestors.push(pointer);
+		let empty = nested.iter().all(|segment| segment_is_empty_within(segment, ancestors));
+		ancestors.pop();
+		empty
+	} else {
+		interactive.to_string().is_empty()
+	}
+}
+fn segment_is_empty_within(segment: &Segment, ancestors: &mut Vec<*const ()>) -> bool {
+	if let Segment::Interactive(interactive) = segment {
+		interactive_is_empty_within(&*interactive.borrow(), ancestors)
+	} else {
+		segment.to_string().is_empty()
+	}
+}
+/// Number of chars a segment nested within `ancestors` renders to, see [`SegmentExt::rendered_len`].
+fn rendered_len_within(segment: &Segment, ancestors: &mut Vec<*const ()>) -> usize {
+	match segment {
+		Segment::Text(text) => text.chars().count(),
+		Segment::Interactive(interactive) => {
+			let interactive = &*interactive.borrow();
+			let pointer = interactive as *const dyn InteractiveSegment as *const ();
+			if ancestors.contains(&pointer) {
+				1
+			} else if let Some(nested) = interactive.nested_printed_segments() {
+				ancestors.push(pointer);
+				let len = nested.iter().map(|segment| rendered_len_within(segment, ancestors)).sum();
+				ancestors.pop();
+				len
+			} else {
+				interactive.to_string().chars().count()
+			}
+		},
+		Segment::Reference(_) => segment.to_string().chars().count()
+	}
+}
+impl SegmentExt for Segment {
+	fn text(text: impl Into<String>) -> Segment {
+		Segment::Text(text.into())
+	}
+	fn as_text(&self) -> Option<&str> {
+		if let Segment::Text(text) = self {
+			Some(text)
+		} else {
+			None
+		}
+	}
+	fn as_interactive(&self) -> Option<&Rc<RefCell<dyn InteractiveSegment>>> {
+		if let Segment::Interactive(interactive) = self {
+			Some(interactive)
+		} else {
+			None
+		}
+	}
+	fn as_reference(&self) -> Option<&Rc<RefCell<Reference>>> {
+		if let Segment::Reference(reference) = self {
+			Some(reference)
+		} else {
+			None
+		}
+	}
+	fn is_empty(&self) -> bool {
+		segment_is_empty_within(self, &mut vec![])
+	}
+	fn rendered_len(&self) -> usize {
+		rendered_len_within(self, &mut vec![])
+	}
+	fn deep_clone(&self) -> Segment {
+		match self {
+			Segment::Text(text) => Segment::Text(text.clone()),
+			Segment::Interactive(interactive) => Segment::Interactive(
+				interactive.borrow().clone_box().unwrap_or_else(|| Rc::clone(interactive))
+			),
+			Segment::Reference(reference) => {
+				let Reference::Text(key, value) = &*reference.borrow();
+				Segment::Reference(Rc::new(RefCell::new(Reference::Text(key.clone(), value.clone()))))
+			}
+		}
+	}
+}
+
+/// Additions to Reference.
+pub trait ReferenceExt {
+	/// Text the reference stands for, which is also what it displays as.
+	fn resolve(&self) -> String;
+}
+impl ReferenceExt for Reference {
+	fn resolve(&self) -> String {
+		let Reference::Text(_, value) = self;
+		value.clone()
+	}
+}
+
+// Text has no such conversion since both String and Segment are defined outside of this library.
+impl From<Placeholder> for Segment {
+	fn from(placeholder: Placeholder) -> Segment {
+		Segment::Interactive(Rc::new(RefCell::new(placeholder)))
+	}
+}
+impl From<Choice> for Segment {
+	fn from(choice: Choice) -> Segment {
+		Segment::Interactive(Rc::new(RefCell::new(choice)))
+	}
+}
+impl From<Variable> for Segment {
+	fn from(variable: Variable) -> Segment {
+		Segment::Interactive(Rc::new(RefCell::new(variable)))
+	}
+}
+impl From<Code> for Segment {
+	fn from(code: Code) -> Segment {
+		Segment::Interactive(Rc::new(RefCell::new(code)))
+	}
+}
+impl From<ComputedReference> for Segment {
+	fn from(reference: ComputedReference) -> Segment {
+		Segment::Interactive(Rc::new(RefCell::new(reference)))
+	}
+}
+
+/// Segments alike in contents, interactive segments being alike in type and contents rather than pointer.
+/// Interactive segments from outside of this library are compared by type and text.
+fn segments_eq(segs: &[Segment], other_segs: &[Segment]) -> bool {
+	segs.len() == other_segs.len() && segs.iter().zip(other_segs).all(|(seg, other_seg)| segment_eq(seg, other_seg))
+}
+fn segment_eq(seg: &Segment, other_seg: &Segment) -> bool {
+	match (seg, other_seg) {
+		(Segment::Text(text), Segment::Text(other_text)) => text == other_text,
+		(Segment::Reference(reference), Segment::Reference(_)) => *reference.borrow() == *other_seg,
+		(Segment::Interactive(interactive), Segment::Interactive(other_interactive)) => {
+			Rc::ptr_eq(interactive, other_interactive) || nested_interactive_eq(interactive, other_interactive)
+		},
+		_ => false
+	}
+}
+/// Compares interactive segments that may be nested within themselves.
+/// Both sides must nest back into a segment being compared at the same depth alike, so nesting is compared as [`hash_segments`] hashes it.
+fn nested_interactive_eq(interactive: &Rc<RefCell<dyn InteractiveSegment>>, other: &Rc<RefCell<dyn InteractiveSegment>>) -> bool {
+	let pointers = (Rc::as_ptr(interactive) as *const (), Rc::as_ptr(other) as *const ());
+	let nested_in_itself = COMPARING.with(|comparing| {
+		let comparing = comparing.borrow();
+		match (comparing.0.iter().position(|pointer| *pointer == pointers.0), comparing.1.iter().position(|pointer| *pointer == pointers.1)) {
+			(None, None) => None,
+			(depth, other_depth) => Some(depth == other_depth)
+		}
+	});
+	if let Some(eq) = nested_in_itself {
+		return eq
+	}
+	COMPARING.with(|comparing| {
+		let mut comparing = comparing.borrow_mut();
+		comparing.0.push(pointers.0);
+		comparing.1.push(pointers.1);
+	});
+	let eq = interactive_eq(&*interactive.borrow(), &*other.borrow());
+	COMPARING.with(|comparing| {
+		let mut comparing = comparing.borrow_mut();
+		comparing.0.pop();
+		comparing.1.pop();
+	});
+	eq
+}
+fn interactive_eq(interactive: &dyn InteractiveSegment, other: &dyn InteractiveSegment) -> bool {
+	if interactive.get_type() != other.get_type() {
+		return false
+	}
+	if let (Some(placeholder), Some(other)) = (cast_interactive_segment::<Placeholder>(interactive), cast_interactive_segment::<Placeholder>(other)) {
+		placeholder == other
+	} else if let (Some(choice), Some(other)) = (cast_interactive_segment::<Choice>(interactive), cast_interactive_segment::<Choice>(other)) {
+		choice == other
+	} else if let (Some(code), Some(other)) = (cast_interactive_segment::<Code>(interactive), cast_interactive_segment::<Code>(other)) {
+		code == other
+	} else if let (Some(reference), Some(other)) = (cast_interactive_segment::<ComputedReference>(interactive), cast_interactive_segment::<ComputedReference>(other)) {
+		reference.name == other.name
+	} else if let (Some(variable), Some(other)) = (cast_interactive_segment::<Variable>(interactive), cast_interactive_segment::<Variable>(other)) {
+		variable == other
+	} else {
+		interactive.to_string() == other.to_string()
+	}
+}
+
+/// Copies segments the way [`SegmentExt::deep_clone`] does, except that a segment found in several spots is copied once and shared by all of them.
+#[derive(Default)]
+struct DeepCloner {
+	interactives: Vec<(*const (), Rc<RefCell<dyn InteractiveSegment>>)>,
+	references: Vec<(*const Reference, Rc<RefCell<Reference>>)>
+}
+impl DeepCloner {
+	fn segments(&mut self, segments: &[Segment]) -> Vec<Segment> {
+		segments.iter().map(|segment| self.segment(segment)).collect()
+	}
+	fn segment(&mut self, segment: &Segment) -> Segment {
+		match segment {
+			Segment::Text(text) => Segment::Text(text.clone()),
+			Segment::Interactive(interactive) => Segment::Interactive(self.interactive(interactive)),
+			Segment::Reference(reference) => Segment::Reference(self.reference(reference))
+		}
+	}
+	fn reference(&mut self, reference: &Rc<RefCell<Reference>>) -> Rc<RefCell<Reference>> {
+		let pointer = Rc::as_ptr(reference) as *const Reference;
+		if let Some((_, copy)) = self.references.iter().find(|(original, _)| *original == pointer) {
+			return Rc::clone(copy)
+		}
+		let Reference::Text(key, value) = &*reference.borrow();
+		let copy = Rc::new(RefCell::new(Reference::Text(key.clone(), value.clone())));
+		self.references.push((pointer, Rc::clone(&copy)));
+		copy
+	}
+	/// Copy of an interactive segment, the original itself if it can not be copied.
+	/// The copy is registered before its nested segments are copied so a segment nested within itself stays so.
+	fn interactive(&mut self, interactive: &Rc<RefCell<dyn InteractiveSegment>>) -> Rc<RefCell<dyn InteractiveSegment>> {
+		let pointer = Rc::as_ptr(interactive) as *const ();
+		if let Some((_, copy)) = self.interactives.iter().find(|(original, _)| *original == pointer) {
+			return Rc::clone(copy)
+		}
+		let original = interactive.borrow();
+		let copy: Rc<RefCell<dyn InteractiveSegment>> = if let Some(placeholder) = cast_interactive_segment::<Placeholder>(&*original) {
+			let copy = Rc::new(RefCell::new(Placeholder::new(vec![])));
+			self.interactives.push((pointer, copy.clone()));
+			let default = self.segments(&placeholder.default);
+			let value = placeholder.value.as_ref().map(|value| self.segments(value));
+			*copy.borrow_mut() = Placeholder { default, value, tab_num: placeholder.tab_num };
+			copy
+		} else if let Some(choice) = cast_interactive_segment::<Choice>(&*original) {
+			let copy = Rc::new(RefCell::new(Choice {
+				selected: choice.selected,
+				choices: vec![],
+				tab_num: choice.tab_num,
+				filter: choice.filter.clone()
+			}));
+			self.interactives.push((pointer, copy.clone()));
+			let choices = choice.choices.iter().map(|segs| self.segments(segs)).collect();
+			copy.borrow_mut().choices = choices;
+			copy
+		} else if let Some(variable) = cast_interactive_segment::<Variable>(&*original) {
+			let copy = Rc::new(RefCell::new(Variable {
+				default: vec![],
+				..variable.clone()
+			}));
+			self.interactives.push((pointer, copy.clone()));
+			let default = self.segments(&variable.default);
+			copy.borrow_mut().default = default;
+			copy
+		} else if let Some(reference) = cast_interactive_segment::<ComputedReference>(&*original) {
+			let copy = Rc::new(RefCell::new(reference.clone()));
+			self.interactives.push((pointer, copy.clone()));
+			let source = self.programic(&reference.source);
+			copy.borrow_mut().source = source;
+			copy
+		} else if let Some(code) = cast_interactive_segment::<Code>(&*original) {
+			Rc::new(RefCell::new(code.clone()))
+		} else {
+			Rc::clone(interactive)
+		};
+		if !self.interactives.iter().any(|(original, _)| *original == pointer) {
+			self.interactives.push((pointer, copy.clone()));
+		}
+		copy
+	}
+	fn field(&mut self, field: &Rc<RefCell<dyn Field>>) -> Rc<RefCell<dyn Field>> {
+		as_field(self.interactive(&(Rc::clone(field) as Rc<RefCell<dyn InteractiveSegment>>))).unwrap_or_else(|| Rc::clone(field))
+	}
+	fn programic(&mut self, programic: &Rc<RefCell<dyn Programic>>) -> Rc<RefCell<dyn Programic>> {
+		as_programic(self.interactive(&(Rc::clone(programic) as Rc<RefCell<dyn InteractiveSegment>>))).unwrap_or_else(|| Rc::clone(programic))
+	}
+}
+
+/// Hash in keeping with [`segments_eq`].
+/// A segment nested within itself is hashed as the depth of the segment it nests back into.
+fn hash_segments<H: Hasher>(segs: &[Segment], state: &mut H) {
+	segs.len().hash(state);
+	for seg in segs {
+		match seg {
+			Segment::Text(text) => {
+				0u8.hash(state);
+				text.hash(state);
+			},
+			Segment::Reference(reference) => {
+				1u8.hash(state);
+				let Reference::Text(key, value) = &*reference.borrow();
+				key.hash(state);
+				value.hash(state);
+			},
+			Segment::Interactive(interactive) => {
+				let pointer = Rc::as_ptr(interactive) as *const ();
+				if let Some(depth) = HASHING.with(|hashing| hashing.borrow().iter().position(|hashed| *hashed == pointer)) {
+					3u8.hash(state);
+					depth.hash(state);
+					continue
+				}
+				2u8.hash(state);
+				HASHING.with(|hashing| hashing.borrow_mut().push(pointer));
+				hash_interactive(&*interactive.borrow(), state);
+				HASHING.with(|hashing| hashing.borrow_mut().pop());
+			}
+		}
+	}
+}
+fn hash_interactive<H: Hasher>(interactive: &dyn InteractiveSegment, state: &mut H) {
+	interactive.get_type().hash(state);
+	if let Some(placeholder) = cast_interactive_segment::<Placeholder>(interactive) {
+		placeholder.hash(state);
+	} else if let Some(choice) = cast_interactive_segment::<Choice>(interactive) {
+		choice.hash(state);
+	} else if let Some(code) = cast_interactive_segment::<Code>(interactive) {
+		code.hash(state);
+	} else if let Some(reference) = cast_interactive_segment::<ComputedReference>(interactive) {
+		reference.name.hash(state);
+	} else if let Some(variable) = cast_interactive_segment::<Variable>(interactive) {
+		variable.hash(state);
+	} else {
+		interactive.to_string().hash(state);
+	}
+}
+
+/// New handle to the same segment.
+fn share(segment: &Segment) -> Segment {
+	match segment {
+		Segment::Text(text) => Segment::Text(text.clone()),
+		Segment::Interactive(interactive) => Segment::Interactive(Rc::clone(interactive)),
+		Segment::Reference(reference) => Segment::Reference(Rc::clone(reference))
+	}
+}
+
+/// Depth first walk over segments and the segments nested within them.
+/// Yields new handles to the segments since nested ones live behind a RefCell.
+/// A segment nested within itself is yielded but not walked into again.
+pub struct WalkSegments {
+	stack: Vec<(usize, Segment)>,
+	ancestors: Vec<*const ()>
+}
+impl WalkSegments {
+	pub fn new(segments: &[Segment]) -> WalkSegments {
+		WalkSegments {
+			stack: segments.iter().rev().map(|segment| (0, share(segment))).collect(),
+			ancestors: vec![]
+		}
+	}
+}
+impl Iterator for WalkSegments {
+	type Item = Segment;
+	fn next(&mut self) -> Option<Segment> {
+		let (depth, segment) = self.stack.pop()?;
+		self.ancestors.truncate(depth);
+		if let Segment::Interactive(interactive) = &segment {
+			let pointer = Rc::as_ptr(interactive) as *const ();
+			if !self.ancestors.contains(&pointer) {
+				if let Some(nested) = interactive.borrow().nested_printed_segments() {
+					self.ancestors.push(pointer);
+					self.stack.extend(nested.iter().rev().map(|nested| (depth + 1, share(nested))));
+				}
+			}
+		}
+		Some(segment)
+	}
+}
+
+/// How badly a [`ValidationIssue`] affects a snippet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+	/// Snippet still expands but likely not as the author intended.
+	Warning,
+	/// Snippet expands to missing output.
+	Error
+}
+
+/// Problem found by [`SnippetExt::validate`].
+/// `tab` is the number of the tab holding the choice, if it is a tab.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationIssue {
+	/// Tab numbers skip over these numbers (ie 1, 2, 4 is missing 3).
+	NonContiguousTabs(Vec<usize>),
+	/// Tabs sharing a number are of different types so can not mirror each other.
+	ConflictingMirrors(usize),
+	/// Selected index of a choice is past its last choice.
+	ChoiceIndexOutOfRange {
+		tab: Option<usize>,
+		index: usize,
+		len: usize
+	},
+	/// Choice has nothing to choose from.
+	EmptyChoice(Option<usize>)
+}
+impl ValidationIssue {
+	pub fn severity(&self) -> Severity {
+		match self {
+			ValidationIssue::NonContiguousTabs(_) | ValidationIssue::ConflictingMirrors(_) => Severity::Warning,
+			ValidationIssue::ChoiceIndexOutOfRange { .. } | ValidationIssue::EmptyChoice(_) => Severity::Error
+		}
+	}
+}
+
+/// Expanded text of a snippet, see [`SnippetExt::display`].
+pub struct DisplaySnippet<'a>(&'a Snippet);
+impl fmt::Display for DisplaySnippet<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for seg in &self.0.body {
+			seg.fmt(f)?;
+		}
+		Ok(())
+	}
+}
+
+/// Where the fields of tabs are found within the displayed text of a snippet, in chars.
+struct TabRanges {
+	tabs: Vec<(*const (), usize)>,
+	ancestors: Vec<*const ()>,
+	ranges: Vec<(usize, Range<usize>)>,
+	offset: usize
+}
+impl TabRanges {
+	fn of(snippet: &Snippet) -> TabRanges {
+		let mut ranges = TabRanges {
+			tabs: snippet.tabs.iter().map(|tab| (Rc::as_ptr(&tab.field) as *const (), tab.num)).collect(),
+			ancestors: vec![],
+			ranges: vec![],
+			offset: 0
+		};
+		ranges.segments(&snippet.body);
+		ranges
+	}
+	fn segments(&mut self, segments: &[Segment]) {
+		for segment in segments {
+			if let Segment::Interactive(interactive) = segment {
+				let pointer = Rc::as_ptr(interactive) as *const ();
+				if self.ancestors.contains(&pointer) {
+					// Displayed as `…`, see fmt_nested.
+					self.offset += 1;
+					continue
+				}
+				let start = self.offset;
+				let index = self.ranges.len();
+				let num = self.tabs.iter().find(|(field, _)| *field == pointer).map(|(_, num)| *num);
+				if let Some(num) = num {
+					self.ranges.push((num, start..start));
+				}
+				let interactive = interactive.borrow();
+				if let Some(nested) = interactive.nested_printed_segments() {
+					self.ancestors.push(pointer);
+					self.segments(nested);
+					self.ancestors.pop();
+				} else {
+					self.offset += interactive.to_string().chars().count();
+				}
+				if num.is_some() {
+					self.ranges[index].1.end = self.offset;
+				}
+			} else {
+				self.offset += segment.rendered_len();
+			}
+		}
+	}
+}
+
+/// Orders tabs by number with the final tab 0 last, the order they are navigated in.
+/// Stands in for `Ord` on [`Tab`], which can not be implemented from this library, ie `tabs.sort_by(cmp_tabs)`.
+pub fn cmp_tabs(tab: &Tab, other: &Tab) -> std::cmp::Ordering {
+	(tab.num == 0, tab.num).cmp(&(other.num == 0, other.num))
+}
+
+/// Writes the tree of [`SnippetExt::pretty_debug`].
+struct TreePrinter {
+	tabs: Vec<(*const (), usize)>,
+	ancestors: Vec<*const ()>,
+	out: String
+}
+impl TreePrinter {
+	fn line(&mut self, depth: usize, line: &str) {
+		for _ in 0..depth {
+			self.out.push_str("  ");
+		}
+		self.out.push_str(line);
+		self.out.push('\n');
+	}
+	fn segments(&mut self, segments: &[Segment], depth: usize) {
+		for segment in segments {
+			match segment {
+				Segment::Text(text) => self.line(depth, &format!("text {:?}", text)),
+				Segment::Reference(reference) => {
+					let Reference::Text(key, value) = &*reference.borrow();
+					self.line(depth, &format!("reference {} = {:?}", key, value));
+				},
+				Segment::Interactive(interactive) => self.interactive(interactive, depth)
+			}
+		}
+	}
+	fn interactive(&mut self, rc: &Rc<RefCell<dyn InteractiveSegment>>, depth: usize) {
+		let pointer = Rc::as_ptr(rc) as *const ();
+		let interactive = &*rc.borrow();
+		let mut line = match interactive.kind() {
+			SegmentType::Placeholder => String::from("placeholder"),
+			SegmentType::Choice => cast_interactive_segment::<Choice>(interactive).map_or_else(String::new, |Choice { selected, choices, .. }| {
+				format!("choice selecting {} of {}", selected, choices.len())
+			}),
+			SegmentType::Variable => cast_interactive_segment::<Variable>(interactive).map_or_else(String::new, |variable| format!("variable {}", variable.name)),
+			SegmentType::Code => cast_interactive_segment::<Code>(interactive).map_or_else(String::new, |code| format!("code {:?}", code.code_to_run)),
+			SegmentType::ComputedReference => cast_interactive_segment::<ComputedReference>(interactive).map_or_else(String::new, |reference| format!("computed reference {}", reference.name)),
+			SegmentType::Other(kind) => format!("{} {:?}", kind, interactive.to_string())
+		};
+		if let Some((_, num)) = self.tabs.iter().find(|(field, _)| *field == pointer) {
+			line.push_str(&format!(" (tab {})", num));
+		}
+		if self.ancestors.contains(&pointer) {
+			line.push_str(" …");
+			self.line(depth, &line);
+			return
+		}
+		self.line(depth, &line);
+		self.ancestors.push(pointer);
+		if let Some(Choice { selected, choices, .. }) = cast_interactive_segment::<Choice>(interactive) {
+			for (i, choice) in choices.iter().enumerate() {
+				self.line(depth + 1, &if i == *selected { format!("choice {} (selected)", i) } else { format!("choice {}", i) });
+				self.segments(choice, depth + 2);
+			}
+		} else if let Some(placeholder) = cast_interactive_segment::<Placeholder>(interactive) {
+			self.segments(placeholder.segments(), depth + 1);
+		} else if let Some(variable) = cast_interactive_segment::<Variable>(interactive) {
+			self.segments(&variable.default, depth + 1);
+		}
+		self.ancestors.pop();
+	}
+}
+
+/// Line ending written by [`SnippetExt::normalize_line_endings`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEnding {
+	/// `\n`
+	#[default]
+	Lf,
+	/// `\r\n`
+	CrLf
+}
+impl LineEnding {
+	/// `text` with every line ending made this one.
+	pub fn apply(&self, text: &str) -> String {
+		let lf = text.replace("\r\n", "\n");
+		match self {
+			LineEnding::Lf => lf,
+			LineEnding::CrLf => lf.replace('\n', "\r\n")
+		}
+	}
+}
+
+/// Piece of the flat form of a snippet given by [`SnippetExt::tokens`], as for syntax highlighting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+	/// Text shown, including the output of shell code and the text of references.
+	Text(String),
+	/// Start of the field of the tab of this number.
+	TabStart(usize),
+	/// End of the field of the tab of this number.
+	TabEnd(usize),
+	/// Start of the selected choice of a choice.
+	ChoiceStart,
+	ChoiceEnd,
+	/// Variable of this name, in place of its value.
+	VariableRef(String)
+}
+/// Writes the tokens of [`SnippetExt::tokens`].
+struct Tokenizer {
+	tabs: Vec<(*const (), usize)>,
+	ancestors: Vec<*const ()>,
+	tokens: Vec<Token>
+}
+impl Tokenizer {
+	fn segments(&mut self, segments: &[Segment]) {
+		for segment in segments {
+			if let Segment::Interactive(interactive) = segment {
+				self.interactive(interactive);
+			} else {
+				self.tokens.push(Token::Text(segment.to_string()));
+			}
+		}
+	}
+	fn interactive(&mut self, rc: &Rc<RefCell<dyn InteractiveSegment>>) {
+		let pointer = Rc::as_ptr(rc) as *const ();
+		if self.ancestors.contains(&pointer) {
+			self.tokens.push(Token::Text(String::from("…")));
+			return
+		}
+		let num = self.tabs.iter().find(|(field, _)| *field == pointer).map(|(_, num)| *num);
+		if let Some(num) = num {
+			self.tokens.push(Token::TabStart(num));
+		}
+		self.ancestors.push(pointer);
+		let interactive = &*rc.borrow();
+		if let Some(placeholder) = cast_interactive_segment::<Placeholder>(interactive) {
+			self.segments(placeholder.segments());
+		} else if let Some(Choice { selected, choices, .. }) = cast_interactive_segment::<Choice>(interactive) {
+			self.tokens.push(Token::ChoiceStart);
+			if let Some(choice) = choices.get(*selected) {
+				self.segments(choice);
+			}
+			self.tokens.push(Token::ChoiceEnd);
+		} else if let Some(variable) = cast_interactive_segment::<Variable>(interactive) {
+			self.tokens.push(Token::VariableRef(variable.name.clone()));
+		} else {
+			self.tokens.push(Token::Text(interactive.to_string()));
+		}
+		self.ancestors.pop();
+		if let Some(num) = num {
+			self.tokens.push(Token::TabEnd(num));
+		}
+	}
+}
+
+/// Additions to Snippet.
+pub trait SnippetExt {
+	/// Snippet with nothing in it.
+	/// Stands in for Default, which can not be implemented here for a type of snippet_body.
+	fn new() -> Self where Self: Sized;
+	/// Every segment of the body, nested ones before their following siblings.
+	fn walk_segments(&self) -> WalkSegments;
+	/// Expanded text of the snippet as it currently stands.
+	fn render(&self) -> String;
+	/// Renders into `buf` in place of what it held, so one buffer can be reused for every render.
+	fn render_into(&self, buf: &mut String);
+	/// Like `render` but interactive segments nested more than `max_depth` levels deep are shown as `…`.
+	/// Keeps rendering cheap for snippets nested deeper than is reasonable, see [`SnippetExt::max_depth`].
+	fn render_with_limit(&self, max_depth: usize) -> String;
+	/// Like `render` but every line after the first starts with `indent`, as when the snippet is inserted at an indented spot.
+	/// Lines that come from shell code output or nested defaults are indented the same, empty lines are left empty.
+	fn render_indented(&self, indent: &str) -> String;
+	/// Deepest level of interactive segments in the body, see [`Placeholder::depth`].
+	fn max_depth(&self) -> usize;
+	/// Indented tree of the body for debugging, one segment to a line with nested segments indented beneath.
+	/// Lines show the type of segment and whichever of its tab number, name, code or selected choice it has.
+	fn pretty_debug(&self) -> String;
+	/// Body flattened into what is shown, with the fields of tabs and selected choices marked at their start and end.
+	fn tokens(&self) -> Vec<Token>;
+	/// Displays the expanded text of the snippet, for use in format strings.
+	/// Snippet can not implement Display itself as both live in snippet_body.
+	fn display(&self) -> DisplaySnippet<'_>;
+	/// Tab numbered 0, the final position of the cursor.
+	fn final_tab(&self) -> Option<&Tab>;
+	/// First tab numbered `num`, the rest of its mirrors are in `mirror_groups`.
+	fn find_tab(&self, num: usize) -> Option<&Tab>;
+	/// Mutable form of `find_tab`.
+	fn find_tab_mut(&mut self, num: usize) -> Option<&mut Tab>;
+	/// Tabs in the order they are navigated through.
+	/// Ascending by number with 0 always going last.
+	/// Only the first tab of a number is included.
+	fn navigation_order(&self) -> Vec<&Tab>;
+	/// Tabs grouped by number, tabs sharing a number mirror each other.
+	fn mirror_groups(&self) -> HashMap<usize, Vec<&Tab>>;
+	/// Gives every tab numbered `num` a copy of `content` as if the user typed it in.
+	/// Placeholders show the content while choices select the first choice that renders the same as it, if any.
+	fn propagate_mirror(&mut self, num: usize, content: Vec<Segment>);
+	/// Makes `field` the field of tab `num`, both in the tabs and wherever the old field sits in the body.
+	/// Further tabs of that number, its mirrors, get copies of `field` so they stay fields of their own.
+	fn replace_tab_field(&mut self, num: usize, field: Rc<RefCell<dyn Field>>);
+	/// Numbers the tabs 1 onwards without gaps, in the order their numbers first appear.
+	/// Tabs that shared a number still do and the final tab stays 0.
+	fn renumber_tabs(&mut self);
+	/// Sorts the tabs into navigation order, see [`cmp_tabs`].
+	/// Mirrors keep their order among themselves.
+	fn sort_tabs(&mut self);
+	/// Merges each run of text segments into one throughout the body and everything nested within it, leaving the render as is.
+	/// Fewer segments are quicker to render.
+	fn coalesce_text(&mut self);
+	/// Replaces the text of every text segment with what `f` makes of it, ie to normalize line endings.
+	/// This takes in text nested anywhere, even in choices not selected and fields of tabs outside of the body, but not references.
+	fn map_text(&mut self, f: impl FnMut(&str) -> String);
+	/// Makes every line ending `eol`, in text as with `map_text` and in the values of variables and output of code evaluated so far.
+	fn normalize_line_endings(&mut self, eol: LineEnding);
+	/// Inserts `segment` into the body at `index`, registering it as a tab, programic segment or reference if it is not already.
+	/// A field becomes the tab of its `tab_num` or otherwise one past the highest tab.
+	/// Segments nested within it are not registered.
+	/// Panics if `index` is past the end of the body like `Vec::insert`.
+	fn insert_segment(&mut self, index: usize, segment: Segment);
+	/// [`SnippetExt::insert_segment`] at the end of the body.
+	fn push_segment(&mut self, segment: Segment);
+	/// Removes the segment at `index` of the body, if there is one.
+	/// It is unregistered as a tab, programic segment or reference unless it is still found elsewhere in the body.
+	fn remove_segment(&mut self, index: usize) -> Option<Segment>;
+	/// Appends `other` to the snippet, numbering its tabs on from the highest tab of the snippet so none collide.
+	/// There stays one final tab 0, that of `other` if it has one since it now comes last.
+	/// The field of a final tab dropped this way is left in the body as an ordinary field.
+	fn merge(&mut self, other: Snippet);
+	/// Registered reference whose key is `key`.
+	fn reference(&self, key: &str) -> Option<Rc<RefCell<Reference>>>;
+	/// Text of the registered reference whose key is `key`, or else of the [`ComputedReference`] of that name anywhere in the snippet.
+	fn resolve_reference(&self, key: &str) -> Option<String>;
+	/// Selects choices by `(tab number, index of the choice)`, ie to restore what a user chose.
+	/// Every choice that is a tab of that number is selected, the pairs with no such choice or an index out of its range are given back.
+	fn apply_choices(&mut self, selections: &[(usize, usize)]) -> Result<(), Vec<(usize, usize)>>;
+	/// Evaluates every programic segment, those in `program_filled_text` as well as those nested in the body.
+	/// Carries on past failures and returns all of them.
+	/// Skips shell code as the default [`CodePolicy`] does, see `evaluate_all_with` to run it.
+	fn evaluate_all(&mut self) -> Result<(), Vec<SnippetError>>;
+	/// Like `evaluate_all` but only runs shell code if `policy` allows it.
+	/// Skipped code is left as it was, so code never run before stays empty.
+	fn evaluate_all_with(&mut self, policy: CodePolicy) -> Result<(), Vec<SnippetError>>;
+	/// Resolves every variable, then runs every shell code with the resolved variables in its environment as textmate does.
+	/// Variables that could not be resolved are left out of the environment.
+	/// Shell code is only run if `policy` allows it, skipped code still gets the variables in its environment.
+	fn evaluate_code_with_variable_env(&mut self, policy: CodePolicy) -> Result<(), Vec<SnippetError>>;
+	/// Resolves every variable through `resolver`, whatever each would be resolved through otherwise, runs every shell code and renders.
+	/// Shell code is only run if `policy` allows it.
+	/// Carries on past failures and returns all of them rather than the rendered snippet.
+	fn expand_with_resolver(&mut self, resolver: &dyn VariableResolver, policy: CodePolicy) -> Result<String, Vec<SnippetError>>;
+	/// Number of distinct tab numbers, mirrors count once.
+	fn count_tabstops(&self) -> usize;
+	/// Highest tab number, not counting the final tab 0.
+	fn max_tab_num(&self) -> Option<usize>;
+	/// Lints the snippet for problems that would otherwise go unnoticed until it expands wrong.
+	fn validate(&self) -> Result<(), Vec<ValidationIssue>>;
+	/// Like `trim_empty_tabs` but removes every tab whose field renders to nothing.
+	/// This includes placeholders holding only empty placeholders and choices whose selected choice is empty.
+	fn trim_empty_tabs_deep(&mut self);
+	/// Body in the snippet syntax of visual studio code, as used for the `body` of a `.code-snippets` file.
+	/// Shell code is written as textmate style interpolated backticks.
+	fn to_vscode_body(&self) -> String;
+	/// Body as a language server protocol snippet, for the `insertText` of a completion item with `insertTextFormat` 2.
+	/// Variables and shell code are written as the text they have evaluated to.
+	fn to_lsp_snippet(&self) -> String;
+	/// Body in the snippet syntax of textmate, for the `content` of a `.tmSnippet`.
+	/// Backticks in text are escaped as they start shell code, and transforms change case through the format (`${TM_FILENAME/.+/\U$0/}`).
+	fn to_textmate_body(&self) -> String;
+	/// Copies of every Code of the snippet in the order `evaluate_all` evaluates them.
+	/// The copies can be sent to and evaluated on a worker thread, then given back with `apply_code_jobs`.
+	fn code_jobs(&self) -> CodeJobs;
+	/// Replaces every Code of the snippet with the job at its position in `code_jobs`.
+	/// Code added or removed since the jobs were taken throws off the order.
+	fn apply_code_jobs(&mut self, jobs: CodeJobs);
+	/// Every variable of the snippet, whether in the body, a tab, an unselected choice or `program_filled_text`, each only once.
+	/// Panics if a segment of the snippet is mutably borrowed at the moment.
+	fn collect_variables(&self) -> Vec<Rc<RefCell<Variable>>>;
+	/// Sets the value of every variable named `name` as [`Variable::set_value`] does, so it is kept when evaluating rather than resolved again.
+	fn replace_variable_value(&mut self, name: &str, value: String);
+	/// Every shell code of the snippet, see `collect_variables`.
+	fn collect_code(&self) -> Vec<Rc<RefCell<Code>>>;
+	/// Whether every variable has resolved and every shell code has run successfully, so the snippet is done evaluating.
+	fn is_fully_resolved(&self) -> bool;
+	/// Copy of the snippet that shares no pointers with the original, for undo history and the like.
+	/// Segments shared within the original, such as a field that is a tab and in the body, are shared within the copy too.
+	/// Interactive segments that can not be copied (see [`InteractiveSegmentExt::clone_box`]) stay shared with the original.
+	fn clone_deep(&self) -> Snippet;
+	/// Char offset into `render` where the cursor goes once done, the final tab or otherwise the end.
+	fn caret_offset(&self) -> usize;
+	/// Index into `body` of the segment whose rendered text takes in the byte `offset` of `render`.
+	/// None past the end, segments rendering to nothing take in no offset.
+	fn byte_to_segment_index(&self, offset: usize) -> Option<usize>;
+	/// Char range of `render` taken up by the field of each tab, for selecting it when the tab is reached.
+	/// Mirrored tabs have a range each and tabs nested within tabs come after the tab they are nested in.
+	fn tab_ranges(&self) -> Vec<(usize, Range<usize>)>;
+	/// Edits that turn `previous`, an earlier `render`, into the current one, for updating only what changed in an editor.
+	/// Each edit is the char range of `previous` to replace and the text to replace it with.
+	/// Everything between the common start and end of the two renders is taken as changed, so there is at most one edit.
+	fn diff_render(&self, previous: &str) -> Vec<(Range<usize>, String)>;
+}
+impl SnippetExt for Snippet {
+	fn new() -> Snippet {
+		Snippet {
+			body: vec![],
+			tabs: vec![],
+			program_filled_text: vec![],
+			references: vec![]
+		}
+	}
+	fn walk_segments(&self) -> WalkSegments {
+		WalkSegments::new(&self.body)
+	}
+	fn render(&self) -> String {
+		let mut rendered = String::new();
+		self.render_into(&mut rendered);
+		rendered
+	}
+	fn render_with_limit(&self, max_depth: usize) -> String {
+		let mut rendered = String::new();
+		render_limited(&mut rendered, &self.body, max_depth);
+		rendered
+	}
+	fn render_indented(&self, indent: &str) -> String {
+		let rendered = self.render();
+		let mut indented = String::with_capacity(rendered.len());
+		for (i, line) in rendered.split('\n').enumerate() {
+			if i > 0 {
+				indented.push('\n');
+				if !line.is_empty() {
+					indented.push_str(indent);
+				}
+			}
+			indented.push_str(line);
+		}
+		indented
+	}
+	fn max_depth(&self) -> usize {
+		segments_depth(&self.body, &mut vec![])
+	}
+	fn tokens(&self) -> Vec<Token> {
+		let mut tokenizer = Tokenizer {
+			tabs: self.tabs.iter().map(|tab| (Rc::as_ptr(&tab.field) as *const (), tab.num)).collect(),
+			ancestors: vec![],
+			tokens: vec![]
+		};
+		tokenizer.segments(&self.body);
+		tokenizer.tokens
+	}
+	fn pretty_debug(&self) -> String {
+		let mut tree = TreePrinter {
+			tabs: self.tabs.iter().map(|tab| (Rc::as_ptr(&tab.field) as *const (), tab.num)).collect(),
+			ancestors: vec![],
+			out: String::new()
+		};
+		tree.segments(&self.body, 0);
+		tree.out
+	}
+	fn render_into(&self, buf: &mut String) {
+		use fmt::Write;
+		buf.clear();
+		let _ = write!(buf, "{}", self.display());
+	}
+	fn display(&self) -> DisplaySnippet<'_> {
+		DisplaySnippet(self)
+	}
+	fn final_tab(&self) -> Option<&Tab> {
+		self.find_tab(0)
+	}
+	fn find_tab(&self, num: usize) -> Option<&Tab> {
+		self.tabs.iter().find(|tab| tab.num == num)
+	}
+	fn find_tab_mut(&mut self, num: usize) -> Option<&mut Tab> {
+		self.tabs.iter_mut().find(|tab| tab.num == num)
+	}
+	fn navigation_order(&self) -> Vec<&Tab> {
+		let mut order: Vec<&Tab> = vec![];
+		for tab in &self.tabs {
+			if !order.iter().any(|ordered| ordered.num == tab.num) {
+				order.push(tab);
+			}
+		}
+		order.sort_by(|tab, other| cmp_tabs(tab, other));
+		order
+	}
+	fn mirror_groups(&self) -> HashMap<usize, Vec<&Tab>> {
+		let mut groups: HashMap<usize, Vec<&Tab>> = HashMap::new();
+		for tab in &self.tabs {
+			groups.entry(tab.num).or_default().push(tab);
+		}
+		groups
+	}
+	fn propagate_mirror(&mut self, num: usize, content: Vec<Segment>) {
+		let rendered: String = content.iter().map(Segment::to_string).collect();
+		for tab in self.tabs.iter().filter(|tab| tab.num == num) {
+			let field: &mut dyn InteractiveSegment = &mut *tab.field.borrow_mut();
+			if let Some(placeholder) = cast_mut_interactive_segment::<Placeholder>(field) {
+				placeholder.set_value(content.iter().map(Segment::deep_clone).collect());
+			} else if let Some(choice) = cast_mut_interactive_segment::<Choice>(field) {
+				let matching = choice.option_strings().iter().position(|option| *option == rendered);
+				if let Some(matching) = matching {
+					choice.selected = matching;
+				}
+			}
+		}
+	}
+	fn replace_tab_field(&mut self, num: usize, field: Rc<RefCell<dyn Field>>) {
+		<dyn Field>::set_tab_num(&mut *field.borrow_mut(), Some(num));
+		let mut replacements: Vec<(*const (), Rc<RefCell<dyn InteractiveSegment>>)> = vec![];
+		for tab in self.tabs.iter_mut().filter(|tab| tab.num == num) {
+			let new = if replacements.is_empty() {
+				Rc::clone(&field)
+			} else {
+				DeepCloner::default().field(&field)
+			};
+			replacements.push((Rc::as_ptr(&tab.field) as *const (), Rc::clone(&new) as Rc<RefCell<dyn InteractiveSegment>>));
+			tab.field = new;
+		}
+		replace_interactives(&mut self.body, &replacements, &mut vec![]);
+	}
+	fn renumber_tabs(&mut self) {
+		let mut renumbered: Vec<usize> = vec![];
+		for tab in &mut self.tabs {
+			if tab.num != 0 {
+				tab.num = if let Some(index) = renumbered.iter().position(|num| *num == tab.num) {
+					index + 1
+				} else {
+					renumbered.push(tab.num);
+					renumbered.len()
+				};
+			}
+			<dyn Field>::set_tab_num(&mut *tab.field.borrow_mut(), Some(tab.num));
+		}
+	}
+	fn sort_tabs(&mut self) {
+		self.tabs.sort_by(cmp_tabs);
+	}
+	fn coalesce_text(&mut self) {
+		merge_nested_text(&mut self.body, &mut vec![]);
+	}
+	fn normalize_line_endings(&mut self, eol: LineEnding) {
+		self.map_text(|text| eol.apply(text));
+		for variable in self.collect_variables() {
+			let mut variable = variable.borrow_mut();
+			variable.value = eol.apply(&variable.value);
+		}
+		for code in self.collect_code() {
+			let mut code = code.borrow_mut();
+			code.output = eol.apply(&code.output);
+		}
+	}
+	fn map_text(&mut self, mut f: impl FnMut(&str) -> String) {
+		let mut visited = vec![];
+		map_nested_text(&mut self.body, &mut f, &mut visited);
+		for tab in &self.tabs {
+			map_interactive_text(&(Rc::clone(&tab.field) as Rc<RefCell<dyn InteractiveSegment>>), &mut f, &mut visited);
+		}
+	}
+	fn insert_segment(&mut self, index: usize, segment: Segment) {
+		match &segment {
+			Segment::Text(_) => {},
+			Segment::Reference(reference) => if !self.references.iter().any(|other| Rc::ptr_eq(other, reference)) {
+				self.references.push(Rc::clone(reference));
+			},
+			Segment::Interactive(interactive) => {
+				let pointer = Rc::as_ptr(interactive) as *const ();
+				if let Some(field) = as_field(Rc::clone(interactive)) {
+					if !self.tabs.iter().any(|tab| Rc::as_ptr(&tab.field) as *const () == pointer) {
+						let num = <dyn Field>::tab_num(&*field.borrow()).unwrap_or_else(|| self.max_tab_num().map_or(1, |max| max + 1));
+						<dyn Field>::set_tab_num(&mut *field.borrow_mut(), Some(num));
+						self.tabs.push(Tab { num, field });
+					}
+				} else if let Some(programic) = as_programic(Rc::clone(interactive)) {
+					if !self.program_filled_text.iter().any(|other| Rc::as_ptr(other) as *const () == pointer) {
+						self.program_filled_text.push(programic);
+					}
+				}
+			}
+		}
+		self.body.insert(index, segment);
+	}
+	fn merge(&mut self, other: Snippet) {
+		let offset = self.max_tab_num().unwrap_or(0);
+		if other.tabs.iter().any(|tab| tab.num == 0) {
+			self.tabs.retain(|tab| if tab.num == 0 {
+				<dyn Field>::set_tab_num(&mut *tab.field.borrow_mut(), None);
+				false
+			} else {
+				true
+			});
+		}
+		for mut tab in other.tabs {
+			if tab.num != 0 {
+				tab.num += offset;
+			}
+			<dyn Field>::set_tab_num(&mut *tab.field.borrow_mut(), Some(tab.num));
+			self.tabs.push(tab);
+		}
+		self.body.extend(other.body);
+		self.program_filled_text.extend(other.program_filled_text);
+		self.references.extend(other.references);
+	}
+	fn reference(&self, key: &str) -> Option<Rc<RefCell<Reference>>> {
+		self.references.iter().find(|reference| {
+			let Reference::Text(reference_key, _) = &*reference.borrow();
+			reference_key == key
+		}).cloned()
+	}
+	fn resolve_reference(&self, key: &str) -> Option<String> {
+		if let Some(reference) = self.reference(key) {
+			Some(reference.borrow().resolve())
+		} else {
+			collect_interactive::<ComputedReference>(self).into_iter().find(|reference| reference.borrow().name == key).map(|reference| reference.borrow().resolve())
+		}
+	}
+	fn push_segment(&mut self, segment: Segment) {
+		self.insert_segment(self.body.len(), segment);
+	}
+	fn remove_segment(&mut self, index: usize) -> Option<Segment> {
+		if index >= self.body.len() {
+			return None
+		}
+		let removed = self.body.remove(index);
+		match &removed {
+			Segment::Text(_) => {},
+			Segment::Reference(reference) => {
+				let still_found = self.walk_segments().any(|segment| segment.as_reference().is_some_and(|other| Rc::ptr_eq(other, reference)));
+				if !still_found {
+					self.references.retain(|other| !Rc::ptr_eq(other, reference));
+				}
+			},
+			Segment::Interactive(interactive) => {
+				let still_found = self.walk_segments().any(|segment| segment.as_interactive().is_some_and(|other| Rc::ptr_eq(other, interactive)));
+				if !still_found {
+					let pointer = Rc::as_ptr(interactive) as *const ();
+					self.tabs.retain(|tab| Rc::as_ptr(&tab.field) as *const () != pointer);
+					self.program_filled_text.retain(|programic| Rc::as_ptr(programic) as *const () != pointer);
+				}
+			}
+		}
+		Some(removed)
+	}
+	fn apply_choices(&mut self, selections: &[(usize, usize)]) -> Result<(), Vec<(usize, usize)>> {
+		let mut invalid = vec![];
+		for &(num, index) in selections {
+			let mut found = false;
+			for tab in self.tabs.iter().filter(|tab| tab.num == num) {
+				if let Some(choice) = cast_mut_field::<Choice>(&mut *tab.field.borrow_mut()) {
+					found |= choice.select(index).is_ok();
+				}
+			}
+			if !found {
+				invalid.push((num, index));
+			}
+		}
+		if invalid.is_empty() {
+			Ok(())
+		} else {
+			Err(invalid)
+		}
+	}
+	fn evaluate_all(&mut self) -> Result<(), Vec<SnippetError>> {
+		self.evaluate_all_with(CodePolicy::default())
+	}
+	fn evaluate_code_with_variable_env(&mut self, policy: CodePolicy) -> Result<(), Vec<SnippetError>> {
+		let mut errors = vec![];
+		let mut env: Vec<(String, String)> = vec![];
+		for variable in self.collect_variables() {
+			let mut variable = variable.borrow_mut();
+			match variable.try_evaluate() {
+				Ok(()) => env.push((variable.name.clone(), variable.value.clone())),
+				Err(err) => errors.push(err)
+			}
+		}
+		for code in self.collect_code() {
+			let mut code = code.borrow_mut();
+			for (name, value) in &env {
+				if let Some(existing) = code.env.iter_mut().find(|(existing, _)| existing == name) {
+					existing.1 = value.clone();
+				} else {
+					code.env.push((name.clone(), value.clone()));
+				}
+			}
+			if policy == CodePolicy::Run {
+				if let Err(err) = code.try_evaluate() {
+					errors.push(err);
+				}
+			}
+		}
+		if errors.is_empty() {
+			Ok(())
+		} else {
+			Err(errors)
+		}
+	}
+	fn expand_with_resolver(&mut self, resolver: &dyn VariableResolver, policy: CodePolicy) -> Result<String, Vec<SnippetError>> {
+		let mut errors = vec![];
+		for variable in self.collect_variables() {
+			if let Err(err) = variable.borrow_mut().try_evaluate_with(resolver) {
+				errors.push(err);
+			}
+		}
+		if policy == CodePolicy::Run {
+			for code in self.collect_code() {
+				if let Err(err) = code.borrow_mut().try_evaluate() {
+					errors.push(err);
+				}
+			}
+		}
+		if errors.is_empty() {
+			Ok(self.render())
+		} else {
+			Err(errors)
+		}
+	}
+	fn evaluate_all_with(&mut self, policy: CodePolicy) -> Result<(), Vec<SnippetError>> {
+		let mut errors = vec![];
+		let mut evaluated: Vec<*const ()> = vec![];
+		for programic in &self.program_filled_text {
+			evaluated.push(Rc::as_ptr(programic) as *const ());
+			let programic = &mut *programic.borrow_mut();
+			let result = if let Some(result) = try_evaluate_segment(programic, policy) {
+				result
+			} else {
+				programic.evaluate();
+				Ok(())
+			};
+			if let Err(err) = result {
+				errors.push(err);
+			}
+		}
+		for segment in self.walk_segments() {
+			if let Segment::Interactive(interactive) = segment {
+				let pointer = Rc::as_ptr(&interactive) as *const ();
+				if evaluated.contains(&pointer) {
+					continue
+				}
+				evaluated.push(pointer);
+				if let Some(Err(err)) = try_evaluate_segment(&mut *interactive.borrow_mut(), policy) {
+					errors.push(err);
+				}
+			}
+		}
+		if errors.is_empty() {
+			Ok(())
+		} else {
+			Err(errors)
+		}
+	}
+	fn count_tabstops(&self) -> usize {
+		self.navigation_order().len()
+	}
+	fn max_tab_num(&self) -> Option<usize> {
+		self.tabs.iter().map(|tab| tab.num).filter(|num| *num != 0).max()
+	}
+	fn validate(&self) -> Result<(), Vec<ValidationIssue>> {
+		let mut issues = vec![];
+		let groups = self.mirror_groups();
+		if let Some(max) = self.max_tab_num() {
+			let missing: Vec<usize> = (1..max).filter(|num| !groups.contains_key(num)).collect();
+			if !missing.is_empty() {
+				issues.push(ValidationIssue::NonContiguousTabs(missing));
+			}
+		}
+		let mut nums: Vec<&usize> = groups.keys().collect();
+		nums.sort();
+		for num in nums {
+			let types: Vec<String> = groups[num].iter().map(|tab| String::from(tab.field.borrow().get_type())).collect();
+			if types.iter().any(|field_type| *field_type != types[0]) {
+				issues.push(ValidationIssue::ConflictingMirrors(*num));
+			}
+		}
+		let mut checked: Vec<*const ()> = vec![];
+		let mut check_choice = |interactive: &dyn InteractiveSegment, pointer: *const (), tab: Option<usize>| {
+			if checked.contains(&pointer) {
+				return
+			}
+			checked.push(pointer);
+			if let Some(Choice { selected: index, choices, .. }) = cast_interactive_segment::<Choice>(interactive) {
+				if choices.is_empty() {
+					issues.push(ValidationIssue::EmptyChoice(tab));
+				} else if *index >= choices.len() {
+					issues.push(ValidationIssue::ChoiceIndexOutOfRange {
+						tab,
+						index: *index,
+						len: choices.len()
+					});
+				}
+			}
+		};
+		for tab in &self.tabs {
+			check_choice(&*tab.field.borrow(), Rc::as_ptr(&tab.field) as *const (), Some(tab.num));
+		}
+		for segment in self.walk_segments() {
+			if let Segment::Interactive(interactive) = segment {
+				check_choice(&*interactive.borrow(), Rc::as_ptr(&interactive) as *const (), None);
+			}
+		}
+		if issues.is_empty() {
+			Ok(())
+		} else {
+			Err(issues)
+		}
+	}
+	fn trim_empty_tabs_deep(&mut self) {
+		self.tabs.retain(|tab| !interactive_is_empty(&*tab.field.borrow()));
+	}
+	fn to_vscode_body(&self) -> String {
+		export::Exporter::new(self, export::Dialect::VsCode).body(&self.body)
+	}
+	fn to_lsp_snippet(&self) -> String {
+		export::Exporter::new(self, export::Dialect::Lsp).body(&self.body)
+	}
+	fn to_textmate_body(&self) -> String {
+		export::Exporter::new(self, export::Dialect::TextMate).body(&self.body)
+	}
+	fn code_jobs(&self) -> CodeJobs {
+		let mut jobs = vec![];
+		for_each_code(self, |code| jobs.push(code.clone()));
+		CodeJobs(jobs)
+	}
+	fn apply_code_jobs(&mut self, jobs: CodeJobs) {
+		let mut jobs = jobs.0.into_iter();
+		for_each_code(self, |code| if let Some(job) = jobs.next() {
+			*code = job;
+		});
+	}
+	fn collect_variables(&self) -> Vec<Rc<RefCell<Variable>>> {
+		collect_interactive(self)
+	}
+	fn collect_code(&self) -> Vec<Rc<RefCell<Code>>> {
+		collect_interactive(self)
+	}
+	fn replace_variable_value(&mut self, name: &str, value: String) {
+		for variable in self.collect_variables() {
+			let mut variable = variable.borrow_mut();
+			if variable.name == name {
+				variable.set_value(value.clone());
+			}
+		}
+	}
+	fn is_fully_resolved(&self) -> bool {
+		self.collect_variables().iter().all(|variable| variable.borrow().is_resolved()) && self.collect_code().iter().all(|code| code.borrow().is_evaluated())
+	}
+	fn byte_to_segment_index(&self, offset: usize) -> Option<usize> {
+		let mut end = 0;
+		self.body.iter().position(|segment| {
+			end += segment.to_string().len();
+			offset < end
+		})
+	}
+	fn caret_offset(&self) -> usize {
+		let ranges = TabRanges::of(self);
+		ranges.ranges.iter().find(|(num, _)| *num == 0).map_or(ranges.offset, |(_, range)| range.start)
+	}
+	fn tab_ranges(&self) -> Vec<(usize, Range<usize>)> {
+		TabRanges::of(self).ranges
+	}
+	fn diff_render(&self, previous: &str) -> Vec<(Range<usize>, String)> {
+		let current: Vec<char> = self.render().chars().collect();
+		let previous: Vec<char> = previous.chars().collect();
+		if current == previous {
+			return vec![]
+		}
+		let prefix = current.iter().zip(&previous).take_while(|(c, p)| c == p).count();
+		let suffix = current[prefix..].iter().rev().zip(previous[prefix..].iter().rev()).take_while(|(c, p)| c == p).count();
+		vec![(prefix..previous.len() - suffix, current[prefix..current.len() - suffix].iter().collect())]
+	}
+	fn clone_deep(&self) -> Snippet {
+		let mut cloner = DeepCloner::default();
+		let body = cloner.segments(&self.body);
+		Snippet {
+			body,
+			tabs: self.tabs.iter().map(|tab| Tab {
+				num: tab.num,
+				field: cloner.field(&tab.field)
+			}).collect(),
+			program_filled_text: self.program_filled_text.iter().map(|programic| cloner.programic(programic)).collect(),
+			references: self.references.iter().map(|reference| cloner.reference(reference)).collect()
+		}
+	}
+}
+/// Every distinct interactive segment of type T found in a snippet, in the order they are come across.
+/// Unlike [`WalkSegments`] this looks into every choice of a choice, not only the selected one.
+fn collect_interactive<T: InteractiveSegment + 'static>(snippet: &Snippet) -> Vec<Rc<RefCell<T>>> {
+	let mut roots: Vec<Rc<RefCell<dyn InteractiveSegment>>> = vec![];
+	collect_nested(&snippet.body, &mut roots);
+	for tab in &snippet.tabs {
+		collect_from(Rc::clone(&tab.field) as Rc<RefCell<dyn InteractiveSegment>>, &mut roots);
+	}
+	for programic in &snippet.program_filled_text {
+		collect_from(Rc::clone(programic) as Rc<RefCell<dyn InteractiveSegment>>, &mut roots);
+	}
+	roots.into_iter().filter_map(|interactive| downcast_rc::<T>(interactive).ok()).collect()
+}
+fn collect_nested(segments: &[Segment], collected: &mut Vec<Rc<RefCell<dyn InteractiveSegment>>>) {
+	for segment in segments {
+		if let Segment::Interactive(interactive) = segment {
+			collect_from(Rc::clone(interactive), collected);
+		}
+	}
+}
+fn collect_from(interactive: Rc<RefCell<dyn InteractiveSegment>>, collected: &mut Vec<Rc<RefCell<dyn InteractiveSegment>>>) {
+	if collected.iter().any(|other| Rc::ptr_eq(other, &interactive)) {
+		return
+	}
+	collected.push(Rc::clone(&interactive));
+	let interactive = interactive.borrow();
+	if let Some(Choice { choices, .. }) = cast_interactive_segment::<Choice>(&*interactive) {
+		for choice in choices {
+			collect_nested(choice, collected);
+		}
+	} else if let Some(nested) = interactive.nested_printed_segments() {
+		collect_nested(nested, collected);
+	}
+}
+/// Swaps every interactive segment found within `segments` for its replacement, leaving the old one to be dropped.
+/// The replacements themselves are not looked into.
+fn replace_interactives(segments: &mut [Segment], replacements: &[(*const (), Rc<RefCell<dyn InteractiveSegment>>)], visited: &mut Vec<*const ()>) {
+	for segment in segments {
+		if let Segment::Interactive(interactive) = segment {
+			let pointer = Rc::as_ptr(interactive) as *const ();
+			if let Some((_, new)) = replacements.iter().find(|(old, _)| *old == pointer) {
+				*interactive = Rc::clone(new);
+			} else if !visited.contains(&pointer) {
+				visited.push(pointer);
+				for nested in every_nested_mut(&mut *interactive.borrow_mut()) {
+					replace_interactives(nested, replacements, visited);
+				}
+			}
+		}
+	}
+}
+/// Every list of segments held by an interactive segment of this library, whether shown or not.
+/// These are every choice of a choice, the default and value of a placeholder and the default of a variable.
+fn every_nested_mut(interactive: &mut dyn InteractiveSegment) -> Vec<&mut Vec<Segment>> {
+	if cast_interactive_segment::<Choice>(interactive).is_some() {
+		cast_mut_interactive_segment::<Choice>(interactive).map_or(vec![], |choice| choice.choices.iter_mut().collect())
+	} else if cast_interactive_segment::<Placeholder>(interactive).is_some() {
+		cast_mut_interactive_segment::<Placeholder>(interactive).map_or(vec![], |placeholder| {
+			let mut nested = vec![&mut placeholder.default];
+			nested.extend(placeholder.value.as_mut());
+			nested
+		})
+	} else {
+		cast_mut_interactive_segment::<Variable>(interactive).map_or(vec![], |variable| vec![&mut variable.default])
+	}
+}
+/// Merges each run of text segments into one.
+fn merge_text(segments: &mut Vec<Segment>) {
+	let mut merged: Vec<Segment> = Vec::with_capacity(segments.len());
+	for segment in segments.drain(..) {
+		if let (Segment::Text(text), Some(Segment::Text(previous))) = (&segment, merged.last_mut()) {
+			previous.push_str(text);
+		} else {
+			merged.push(segment);
+		}
+	}
+	*segments = merged;
+}
+/// Replaces the text of every text segment within `segments`, nested ones included, with what `f` makes of it.
+fn map_nested_text(segments: &mut [Segment], f: &mut dyn FnMut(&str) -> String, visited: &mut Vec<*const ()>) {
+	for segment in segments {
+		match segment {
+			Segment::Text(text) => *text = f(text),
+			Segment::Interactive(interactive) => map_interactive_text(interactive, f, visited),
+			Segment::Reference(_) => {}
+		}
+	}
+}
+/// [`map_nested_text`] for the segments nested within `interactive`.
+fn map_interactive_text(interactive: &Rc<RefCell<dyn InteractiveSegment>>, f: &mut dyn FnMut(&str) -> String, visited: &mut Vec<*const ()>) {
+	let pointer = Rc::as_ptr(interactive) as *const ();
+	if !visited.contains(&pointer) {
+		visited.push(pointer);
+		for nested in every_nested_mut(&mut *interactive.borrow_mut()) {
+			map_nested_text(nested, f, visited);
+		}
+	}
+}
+/// [`merge_text`] for segments and every list of segments nested within them.
+fn merge_nested_text(segments: &mut Vec<Segment>, visited: &mut Vec<*const ()>) {
+	merge_text(segments);
+	for segment in segments.iter() {
+		if let Segment::Interactive(interactive) = segment {
+			let pointer = Rc::as_ptr(interactive) as *const ();
+			if !visited.contains(&pointer) {
+				visited.push(pointer);
+				for nested in every_nested_mut(&mut *interactive.borrow_mut()) {
+					merge_nested_text(nested, visited);
+				}
+			}
+		}
+	}
+}
+/// Calls `f` once with every Code of a snippet, those in `program_filled_text` first.
+fn for_each_code(snippet: &Snippet, mut f: impl FnMut(&mut Code)) {
+	let mut visited: Vec<*const ()> = vec![];
+	for programic in &snippet.program_filled_text {
+		visited.push(Rc::as_ptr(programic) as *const ());
+		if let Some(code) = cast_mut_programic::<Code>(&mut *programic.borrow_mut()) {
+			f(code);
+		}
+	}
+	for segment in snippet.walk_segments() {
+		if let Segment::Interactive(interactive) = segment {
+			let pointer = Rc::as_ptr(&interactive) as *const ();
+			if visited.contains(&pointer) {
+				continue
+			}
+			visited.push(pointer);
+			if let Some(code) = cast_mut_interactive_segment::<Code>(&mut *interactive.borrow_mut()) {
+				f(code);
+			}
+		}
+	}
+}
+/// Evaluates the programic segments of this library through their fallible form.
+/// None for any other segment.
+fn try_evaluate_segment(segment: &mut dyn InteractiveSegment, policy: CodePolicy) -> Option<Result<(), SnippetError>> {
+	if let Some(variable) = cast_mut_interactive_segment::<Variable>(segment) {
+		Some(variable.try_evaluate())
+	} else {
+		let code = cast_mut_interactive_segment::<Code>(segment)?;
+		Some(match policy {
+			CodePolicy::Run => code.try_evaluate(),
+			CodePolicy::Skip => Ok(())
+		})
+	}
+}
+
+/// Position of the user while navigating through the tabs of a snippet.
+/// Kept apart from the snippet so several views can navigate one snippet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TabCursor {
+	/// Index into the navigation order, None before the first tab.
+	position: Option<usize>
+}
+impl TabCursor {
+	pub fn new() -> TabCursor {
+		TabCursor::default()
+	}
+	/// Tab the cursor is on, if any.
+	pub fn current<'a>(&self, snippet: &'a Snippet) -> Option<&'a Tab> {
+		snippet.navigation_order().get(self.position?).copied()
+	}
+	/// Moves on to the following tab.
+	/// None once the last tab has been passed, meaning expansion is complete.
+	pub fn next_tab<'a>(&mut self, snippet: &'a Snippet) -> Option<&'a Tab> {
+		let order = snippet.navigation_order();
+		let position = self.position.map_or(0, |position| position + 1).min(order.len());
+		self.position = Some(position);
+		order.get(position).copied()
+	}
+	/// Moves back to the preceding tab.
+	/// None once moved back before the first tab.
+	pub fn prev_tab<'a>(&mut self, snippet: &'a Snippet) -> Option<&'a Tab> {
+		let order = snippet.navigation_order();
+		match self.position {
+			Some(position) if position > 0 && !order.is_empty() => {
+				let position = position.min(order.len()) - 1;
+				self.position = Some(position);
+				order.get(position).copied()
+			},
+			_ => {
+				self.position = None;
+				None
+			}
+		}
+	}
+	/// Moves back to the first tab.
+	pub fn reset_tabs<'a>(&mut self, snippet: &'a Snippet) -> Option<&'a Tab> {
+		self.position = None;
+		self.next_tab(snippet)
+	}
+}
+
+/// Puts together a snippet segment by segment, registering tabs and programic segments as they are added.
+#[derive(Debug, Default)]
+pub struct SnippetBuilder {
+	body: Vec<Segment>,
+	tabs: Vec<Tab>,
+	program_filled_text: Vec<Rc<RefCell<dyn Programic>>>,
+	references: Vec<Rc<RefCell<Reference>>>
+}
+impl SnippetBuilder {
+	pub fn new() -> SnippetBuilder {
+		SnippetBuilder::default()
+	}
+	/// Any segment, its tab or programic part registered by hand if need be.
+	pub fn segment(mut self, segment: Segment) -> SnippetBuilder {
+		self.body.push(segment);
+		self
+	}
+	pub fn text(self, text: impl Into<String>) -> SnippetBuilder {
+		self.segment(Segment::Text(text.into()))
+	}
+	/// Placeholder that is tab `num`, tabs sharing a number mirror each other.
+	pub fn placeholder(self, num: usize, segs: Vec<Segment>) -> SnippetBuilder {
+		self.field(num, Rc::new(RefCell::new(Placeholder::new(segs))))
+	}
+	/// Choice that is tab `num` with the first of `branches` selected.
+	pub fn choice(self, num: usize, branches: Vec<Vec<Segment>>) -> SnippetBuilder {
+		self.field(num, Rc::new(RefCell::new(Choice::new(branches))))
+	}
+	fn field<T: Field + 'static>(mut self, num: usize, field: Rc<RefCell<T>>) -> SnippetBuilder {
+		<dyn Field>::set_tab_num(&mut *field.borrow_mut(), Some(num));
+		self.tabs.push(Tab {
+			num,
+			field: field.clone()
+		});
+		self.segment(Segment::Interactive(field))
+	}
+	/// Variable looked up in the environment.
+	pub fn variable(self, name: impl Into<String>) -> SnippetBuilder {
+		self.programic(Rc::new(RefCell::new(Variable::new(name))))
+	}
+	/// Shell code run by the system shell.
+	pub fn code(self, code_to_run: impl Into<String>) -> SnippetBuilder {
+		self.programic(Rc::new(RefCell::new(Code::new(code_to_run))))
+	}
+	fn programic<T: Programic + 'static>(mut self, programic: Rc<RefCell<T>>) -> SnippetBuilder {
+		self.program_filled_text.push(programic.clone());
+		self.segment(Segment::Interactive(programic))
+	}
+	pub fn reference(mut self, key: impl Into<String>, value: impl Into<String>) -> SnippetBuilder {
+		let reference = Rc::new(RefCell::new(Reference::Text(key.into(), value.into())));
+		self.references.push(Rc::clone(&reference));
+		self.segment(Segment::Reference(reference))
+	}
+	pub fn build(self) -> Snippet {
+		Snippet {
+			body: self.body,
+			tabs: self.tabs,
+			program_filled_text: self.program_filled_text,
+			references: self.references
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn trait_object_casting() {
+		let mut snippet = Snippet {
+			body: vec![],
+			tabs: vec![],
+			program_filled_text: vec![],
+			references: vec![]
+		};
+		let placeholder = Placeholder::new(vec![
+			Segment::Text(String::from("hello")),
+			Segment::Text(String::from("there!"))
+		]);
+		let placeholder_rc = Rc::new(RefCell::new(placeholder));
+		let placeholder_clone = Rc::clone(&placeholder_rc);
+		let tab = Tab {
+			num: 1,
+			field: placeholder_clone
+		};
+		let mut code = Code::new("greet=hi echo no");
+		code.evaluate();
+		let code_rc = Rc::new(RefCell::new(code));
+		let code_clone = Rc::clone(&code_rc);
+		snippet.program_filled_text.push(code_clone);
+		let _placeholder_weak = Rc::downgrade(&placeholder_rc);
+		let mut placeholder = Placeholder::new(vec![
+			Segment::Interactive(placeholder_rc)
+		]);
 		let interactive = &snippet.program_filled_text[0];
 		let interactive = &mut *interactive.borrow_mut();
 		let interactive: &mut Code = cast_mut_programic(interactive).unwrap();
 		interactive.code_to_run = String::from("greet=hi echo yes");
 		interactive.evaluate();
 		println!("return of code runned: {}", interactive);
-		if let Placeholder(ref mut segs) = placeholder {
+		{
+			let Placeholder { default: ref mut segs, .. } = placeholder;
 			if let Segment::Interactive(interactive) = &segs[0] {
 				let interactive = &*interactive.borrow_mut();
 				let placeholder: &Placeholder = cast_interactive_segment(interactive).unwrap();
-				if let None = cast_interactive_segment::<Choice>(interactive) {
+				if cast_interactive_segment::<Choice>(interactive).is_none() {
 					println!("Not a choice!");
 				}
-				if let Placeholder(ref segs) = placeholder {
-					println!("Placeholder length: {}", segs.len());
-					println!("Second segment: {}", &segs[1]);
+				let Placeholder { default: ref segs, .. } = placeholder;
+				println!("Placeholder length: {}", segs.len());
+				println!("Second segment: {}", &segs[1]);
+			}
+//			segs[0] = Segment::Text(String::from("dud!"));
+		}
+		let field_rc = tab.field;
+		println!("Reference!");
+		let field = &*field_rc.borrow();
+		let placeholder: &Placeholder = cast_field(field).unwrap();
+		println!("Just making sure! {}", placeholder);
+		println!("Just making sure again! {}", placeholder);
+	}
+	#[test]
+	fn partial_eq_test() {
+		let reference = Reference::Text(String::from("Greetings"), String::from("Hi"));
+		let reference = Rc::new(RefCell::new(reference));
+		let segment = Segment::Reference(Rc::clone(&reference));
+		assert!(*reference.borrow() == segment, "Reference not equal to segment!");
+		println!("{}", *reference.borrow() == segment);
+	}
+	#[test]
+	fn trim_empty_test() {
+		let field: Rc<RefCell<dyn Field>> = Rc::new(RefCell::new(Placeholder::new(
+			vec![Segment::Text(
+				String::from("Hello ")
+			)]
+		)));
+		let mut snip = Snippet {
+			body: vec![],
+			tabs: vec![Tab {
+				num: 1,
+				field: Rc::clone(&field)
+			}],
+			program_filled_text: vec![],
+			references: vec![],
+		};
+		println!("tab 1 count: {}", Rc::strong_count(&snip.tabs[0].field));
+		snip.trim_empty_tabs();
+		println!("tab 1 count: {}", Rc::strong_count(&snip.tabs[0].field));
+		println!("number of tabs: {}", snip.tabs.len());
+	}
+	#[test]
+	fn test_debug_impl() {
+		let mut var = Variable::new("test");
+		var.evaluate();
+		println!("{:#?}", var);
+		let var = Rc::new(RefCell::new(var));
+		let var_clone = Rc::clone(&var);
+		let text = Segment::Text(String::from("Hello! "));
+		println!("{:#?}", text);
+		let snip = Snippet {
+			body: vec![text, Segment::Interactive(var)],
+			tabs: vec![],
+			program_filled_text: vec![var_clone],
+			references: vec![],
+		};
+		println!("{:#?}", snip);
+	}
+	#[test]
+	fn resolver_survives_move() {
+		let mut calls = 0;
+		let var = Variable::with_resolver("greeting", move |name| {
+			calls += 1;
+			format!("{} #{}", name, calls)
+		});
+		let mut vars = vec![var];
+		let mut var = vars.remove(0);
+		var.evaluate();
+		assert_eq!(var.value, "greeting #1");
+		var.evaluate();
+		assert_eq!(var.value, "greeting #2");
+	}
+	#[test]
+	fn unset_variable_not_found() {
+		let mut var = Variable::new("SNIPPET_SEGMENT_TYPES_UNSET");
+		var.value = String::from("stale");
+		std::env::remove_var(&var.name);
+		assert_eq!(var.try_evaluate(), Err(SnippetError::VariableNotFound(var.name.clone())));
+		assert_eq!(var.value, "");
+		var.evaluate();
+		assert_eq!(var.value, "");
+	}
+	#[test]
+	fn empty_variable_is_found() {
+		let mut var = Variable::new("SNIPPET_SEGMENT_TYPES_EMPTY");
+		std::env::set_var(&var.name, "");
+		assert_eq!(var.try_evaluate(), Ok(()));
+		assert_eq!(var.value, "");
+	}
+	#[test]
+	fn resolver_empty_value_is_found() {
+		let mut var = Variable::with_resolver("nothing", |_| String::new());
+		assert_eq!(var.try_evaluate(), Ok(()));
+		assert_eq!(var.value, "");
+		let mut var = Variable::with_resolver("something", |name| name.to_uppercase());
+		assert_eq!(var.try_evaluate(), Ok(()));
+		assert_eq!(var.value, "SOMETHING");
+	}
+	#[test]
+	fn failing_code_reports_exit_code() {
+		let mut code = Code::new("echo partial; exit 3");
+		match code.try_evaluate() {
+			Err(SnippetError::ScriptFailed { code: exit_code, .. }) => assert_eq!(exit_code, Some(3)),
+			other => panic!("expected script failure, got {:?}", other)
+		}
+		assert_eq!(code.last_exit_code, Some(3));
+		assert_eq!(code.output, "");
+		code.evaluate();
+		assert_eq!(code.output, "");
+	}
+	#[test]
+	fn code_keeps_stderr_and_exit_code() {
+		let mut code = Code::new("echo hi; echo oops 1>&2; exit 2");
+		assert!(code.try_evaluate().is_err());
+		assert_eq!(code.stderr, "oops\n");
+		assert_eq!(code.last_exit_code, Some(2));
+		let mut code = Code::new("echo hi; echo oops 1>&2");
+		code.evaluate();
+		assert_eq!(code.output, "hi\n");
+		assert_eq!(code.stderr, "oops\n");
+		assert_eq!(code.last_exit_code, Some(0));
+		assert_eq!(code.to_string(), "hi\n");
+	}
+	#[test]
+	fn chosen_runner() {
+		let mut code = Code::new("echo x");
+		code.runner = ScriptRunner::Sh;
+		code.try_evaluate().unwrap();
+		assert_eq!(code.output, "x\n");
+		code.runner = ScriptRunner::Custom(String::from("sh"));
+		code.try_evaluate().unwrap();
+		assert_eq!(code.output, "x\n");
+	}
+	#[test]
+	fn python_runner() {
+		for (runner, code) in [(ScriptRunner::Python, "print(\"x\")"), (ScriptRunner::Node, "console.log(\"x\")")] {
+			let program = runner.program().unwrap();
+			if std::process::Command::new(program).arg("--version").output().is_err() {
+				println!("{} is not installed, skipping", program);
+				continue
+			}
+			let mut code = Code::new(code);
+			code.runner = runner;
+			code.try_evaluate().unwrap();
+			assert_eq!(code.output, "x\n");
+			code.timeout = Some(Duration::from_secs(10));
+			code.try_evaluate().unwrap();
+			assert_eq!(code.output, "x\n");
+		}
+	}
+	#[test]
+	fn code_timeout() {
+		let mut code = Code::new("sleep 5");
+		code.timeout = Some(Duration::from_millis(100));
+		let started = Instant::now();
+		assert_eq!(code.try_evaluate(), Err(SnippetError::Timeout));
+		assert!(started.elapsed() < Duration::from_secs(5));
+		let mut code = Code::new("echo quick");
+		code.timeout = Some(Duration::from_secs(5));
+		code.try_evaluate().unwrap();
+		assert_eq!(code.output, "quick\n");
+	}
+	#[test]
+	fn code_env_and_args() {
+		let mut code = Code::new("echo $GREET $1");
+		code.env = vec![(String::from("GREET"), String::from("hi"))];
+		code.args = vec![String::from("there")];
+		code.try_evaluate().unwrap();
+		assert_eq!(code.output, "hi there\n");
+	}
+	#[test]
+	fn deep_clone_nested() {
+		let choice = Rc::new(RefCell::new(Choice::new(vec![
+			vec![Segment::Text(String::from("one"))],
+			vec![Segment::Text(String::from("two"))]
+		])));
+		let original = Placeholder::new(vec![
+			Segment::Text(String::from("pick ")),
+			Segment::Interactive(choice.clone())
+		]);
+		let copy = original.clone();
+		if let Segment::Interactive(interactive) = &copy.default[1] {
+			assert!(!std::ptr::addr_eq(Rc::as_ptr(interactive), Rc::as_ptr(&choice)));
+		}
+		choice.borrow_mut().selected = 1;
+		assert_eq!(original.to_string(), "pick two");
+		assert_eq!(copy.to_string(), "pick one");
+		let source: Rc<RefCell<dyn Programic>> = Rc::new(RefCell::new(Variable::with_resolver("NAME", |_| String::from("zakhar"))));
+		let reference: Rc<RefCell<dyn InteractiveSegment>> = Rc::new(RefCell::new(ComputedReference::new("name", source.clone())));
+		let copied = reference.borrow().clone_box().unwrap();
+		assert!(!Rc::ptr_eq(&copied, &reference));
+		let shared = cast_interactive_segment::<ComputedReference>(&*copied.borrow()).map(|copied| (copied.name.clone(), Rc::ptr_eq(&copied.source, &source)));
+		assert_eq!(shared, Some((String::from("name"), true)));
+		source.borrow_mut().evaluate();
+		assert_eq!(copied.borrow().to_string(), "zakhar");
+	}
+	#[test]
+	fn debug_shows_contents() {
+		let choice: Rc<RefCell<dyn Field>> = Rc::new(RefCell::new(Choice::try_new(1, vec![
+			vec![Segment::Text(String::from("first"))],
+			vec![Segment::Text(String::from("second"))]
+		]).unwrap()));
+		let var = Rc::new(RefCell::new(Variable::with_resolver("USER_NAME", |_| String::from("zakhar"))));
+		var.borrow_mut().evaluate();
+		let code = Rc::new(RefCell::new(Code::new("echo debug")));
+		let snip = Snippet {
+			body: vec![Segment::Interactive(var.clone()), Segment::Interactive(code.clone())],
+			tabs: vec![Tab {
+				num: 1,
+				field: choice
+			}],
+			program_filled_text: vec![var, code],
+			references: vec![]
+		};
+		let debug = format!("{:?}", snip);
+		assert!(debug.contains("Choice { selected: 1, "), "{}", debug);
+		assert!(debug.contains("name: \"USER_NAME\", value: \"zakhar\""), "{}", debug);
+		assert!(debug.contains("get_from_client: Some(\"FnMut\")"), "{}", debug);
+		assert!(debug.contains("code_to_run: \"echo debug\""), "{}", debug);
+	}
+	#[test]
+	fn walk_nested_placeholders() {
+		let inner = Rc::new(RefCell::new(Placeholder::new(vec![
+			Segment::Text(String::from("inner"))
+		])));
+		let outer = Rc::new(RefCell::new(Placeholder::new(vec![
+			Segment::Text(String::from("outer ")),
+			Segment::Interactive(inner)
+		])));
+		let snip = Snippet {
+			body: vec![
+				Segment::Text(String::from("start ")),
+				Segment::Interactive(outer.clone()),
+				Segment::Text(String::from(" end"))
+			],
+			tabs: vec![],
+			program_filled_text: vec![],
+			references: vec![]
+		};
+		let walked: Vec<String> = snip.walk_segments().map(|segment| match segment {
+			Segment::Interactive(interactive) => format!("<{}>", interactive.borrow().get_type()),
+			segment => segment.to_string()
+		}).collect();
+		assert_eq!(walked, ["start ", "<placeholder>", "outer ", "<placeholder>", "inner", " end"]);
+		outer.borrow_mut().default.push(Segment::Interactive(outer.clone()));
+		assert_eq!(snip.walk_segments().count(), 7);
+		outer.borrow_mut().default.pop();
+	}
+	#[test]
+	fn edit_through_trait_object() {
+		let placeholder: Rc<RefCell<dyn InteractiveSegment>> = Rc::new(RefCell::new(Placeholder::new(vec![
+			Segment::Text(String::from("draft"))
+		])));
+		if let Some(segs) = placeholder.borrow_mut().nested_segments_mut() {
+			segs[0] = Segment::Text(String::from("final"));
+		}
+		assert_eq!(placeholder.borrow().to_string(), "final");
+		let choice: Rc<RefCell<dyn InteractiveSegment>> = Rc::new(RefCell::new(Choice::try_new(1, vec![
+			vec![Segment::Text(String::from("a"))],
+			vec![Segment::Text(String::from("b"))]
+		]).unwrap()));
+		choice.borrow_mut().nested_segments_mut().unwrap().push(Segment::Text(String::from("c")));
+		assert_eq!(choice.borrow().to_string(), "bc");
+		let code: Rc<RefCell<dyn InteractiveSegment>> = Rc::new(RefCell::new(Code::new("true")));
+		assert!(code.borrow_mut().nested_segments_mut().is_none());
+	}
+	#[test]
+	fn render_body() {
+		let var = Rc::new(RefCell::new(Variable::with_resolver("NAME", |_| String::from("world"))));
+		var.borrow_mut().evaluate();
+		let snip = Snippet {
+			body: vec![
+				Segment::Text(String::from("Hello ")),
+				Segment::Interactive(Rc::new(RefCell::new(Placeholder::new(vec![
+					Segment::Text(String::from("dear "))
+				])))),
+				Segment::Interactive(var.clone())
+			],
+			tabs: vec![],
+			program_filled_text: vec![var],
+			references: vec![]
+		};
+		assert_eq!(snip.render(), "Hello dear world");
+	}
+	#[test]
+	fn case_transforms() {
+		let cases = [
+			(VariableTransform::Upcase, "hello world", "HELLO WORLD"),
+			(VariableTransform::Downcase, "Hello World", "hello world"),
+			(VariableTransform::Capitalize, "hello world", "Hello world"),
+			(VariableTransform::PascalCase, "hello big_world", "HelloBigWorld"),
+			(VariableTransform::CamelCase, "Hello big-world", "helloBigWorld")
+		];
+		for (transform, input, expected) in cases {
+			let mut var = Variable::with_resolver("TM_FILENAME", move |_| String::from(input));
+			var.transform = Some(transform);
+			var.evaluate();
+			assert_eq!(var.value, expected);
+		}
+		let mut var = Variable::with_resolver("TM_FILENAME", |_| String::from("left alone"));
+		var.evaluate();
+		assert_eq!(var.value, "left alone");
+	}
+	#[cfg(feature = "regex")]
+	#[test]
+	fn regex_transforms() {
+		let first = VariableTransform::regex("(\\w+)-(\\w+)", "${2}_${1}", false).unwrap();
+		assert_eq!(first.apply("a-b c-d"), "b_a c-d");
+		let global = VariableTransform::regex("(\\w+)-(\\w+)", "${2}_${1}", true).unwrap();
+		assert_eq!(global.apply("a-b c-d"), "b_a d_c");
+		assert_eq!(global.apply("nothing to see"), "nothing to see");
+		let mut var = Variable::with_resolver("TM_FILENAME", |_| String::from("main.rs"));
+		var.transform = Some(VariableTransform::regex("\\.rs$", ".txt", false).unwrap());
+		var.evaluate();
+		assert_eq!(var.value, "main.txt");
+		assert!(matches!(VariableTransform::regex("(unclosed", "", false), Err(SnippetError::InvalidRegex(_))));
+	}
+	#[cfg(feature = "chrono")]
+	#[test]
+	fn date_time_transform() {
+		let ymd = VariableTransform::DateTime(String::from("%Y-%m-%d"));
+		assert_eq!(ymd.apply("1709211909"), "2024-02-29");
+		assert_eq!(ymd.apply("2024-02-29T13:05:09Z"), "2024-02-29");
+		assert_eq!(ymd.apply("not a time"), "not a time");
+		// 2024-02-29 13:05:09 UTC
+		let resolver = Rc::new(BuiltinResolver {
+			now: Some(std::time::UNIX_EPOCH + Duration::from_secs(1709211909)),
+			..BuiltinResolver::new()
+		});
+		let mut var = Variable::resolved_by("CURRENT_DATE", resolver);
+		var.transform = Some(ymd);
+		var.try_evaluate().unwrap();
+		println!("{}", var.value);
+		assert_eq!(var.value, "2024-02-29");
+		let mut own = Variable::with_resolver("CURRENT_RELEASE", |_| String::from("2023-06-01T00:00:00Z"));
+		own.transform = Some(VariableTransform::DateTime(String::from("%Y")));
+		own.try_evaluate().unwrap();
+		assert_eq!(own.value, "2023");
+	}
+	#[test]
+	fn placeholder_default_and_value() {
+		let mut placeholder = Placeholder::new(vec![Segment::Text(String::from("name"))]);
+		assert_eq!(placeholder.to_string(), "name");
+		placeholder.set_value(vec![Segment::Text(String::from("zakhar"))]);
+		assert_eq!(placeholder.to_string(), "zakhar");
+		placeholder.set_value(vec![]);
+		assert_eq!(placeholder.to_string(), "");
+		placeholder.clear();
+		assert_eq!(placeholder.to_string(), "name");
+	}
+	#[test]
+	fn choice_navigation() {
+		let mut choice = Choice::new(vec![
+			vec![Segment::Text(String::from("a"))],
+			vec![Segment::Text(String::from("b"))],
+			vec![Segment::Text(String::from("c"))]
+		]);
+		choice.previous();
+		assert_eq!(choice.to_string(), "c");
+		choice.next();
+		assert_eq!(choice.to_string(), "a");
+		choice.next();
+		assert_eq!(choice.to_string(), "b");
+		assert_eq!(choice.select(2), Ok(()));
+		choice.next();
+		assert_eq!(choice.selected, 0);
+		assert_eq!(choice.select(3), Err(SnippetError::ChoiceIndexOutOfRange { index: 3, len: 3 }));
+		assert_eq!(choice.selected, 0);
+	}
+	#[test]
+	fn choice_editing() {
+		let mut choice = Choice::new(vec![]);
+		choice.push_choice(vec![Segment::Text(String::from("a"))]);
+		choice.push_choice(vec![Segment::Text(String::from("c"))]);
+		choice.select(1).unwrap();
+		choice.insert_choice(1, vec![Segment::Text(String::from("b"))]);
+		assert_eq!(choice.to_string(), "c");
+		assert_eq!(choice.selected, 2);
+		assert_eq!(choice.remove_choice(2).map(|segs| segs.len()), Some(1));
+		assert_eq!(choice.selected, 1);
+		assert_eq!(choice.to_string(), "b");
+		choice.remove_choice(0);
+		assert_eq!(choice.selected, 0);
+		assert_eq!(choice.to_string(), "b");
+		choice.remove_choice(0);
+		assert_eq!(choice.selected, 0);
+		assert!(choice.remove_choice(0).is_none());
+	}
+	#[test]
+	fn final_tab_goes_last() {
+		let tab = |num| Tab {
+			num,
+			field: Rc::new(RefCell::new(Placeholder::new(vec![]))) as Rc<RefCell<dyn Field>>
+		};
+		let snip = Snippet {
+			body: vec![],
+			tabs: vec![tab(0), tab(2), tab(1)],
+			program_filled_text: vec![],
+			references: vec![]
+		};
+		let order: Vec<usize> = snip.navigation_order().iter().map(|tab| tab.num).collect();
+		assert_eq!(order, [1, 2, 0]);
+		assert_eq!(snip.final_tab().map(|tab| tab.num), Some(0));
+	}
+	#[test]
+	fn tab_cursor_traversal() {
+		let tab = |num| Tab {
+			num,
+			field: Rc::new(RefCell::new(Placeholder::new(vec![]))) as Rc<RefCell<dyn Field>>
+		};
+		let snip = Snippet {
+			body: vec![],
+			tabs: vec![tab(2), tab(0), tab(1), tab(3)],
+			program_filled_text: vec![],
+			references: vec![]
+		};
+		let mut cursor = TabCursor::new();
+		let mut forward = vec![];
+		while let Some(tab) = cursor.next_tab(&snip) {
+			forward.push(tab.num);
+		}
+		assert_eq!(forward, [1, 2, 3, 0]);
+		assert!(cursor.next_tab(&snip).is_none());
+		let mut backward = vec![];
+		while let Some(tab) = cursor.prev_tab(&snip) {
+			backward.push(tab.num);
+		}
+		assert_eq!(backward, [0, 3, 2, 1]);
+		assert!(cursor.current(&snip).is_none());
+		cursor.next_tab(&snip);
+		cursor.next_tab(&snip);
+		assert_eq!(cursor.current(&snip).map(|tab| tab.num), Some(2));
+		assert_eq!(cursor.reset_tabs(&snip).map(|tab| tab.num), Some(1));
+	}
+	#[test]
+	fn mirrored_tabs() {
+		let first: Rc<RefCell<dyn Field>> = Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from("name"))])));
+		let mirror: Rc<RefCell<dyn Field>> = Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from("name"))])));
+		let choice: Rc<RefCell<dyn Field>> = Rc::new(RefCell::new(Choice::new(vec![
+			vec![Segment::Text(String::from("other"))],
+			vec![Segment::Text(String::from("zakhar"))]
+		])));
+		let mut snip = Snippet {
+			body: vec![],
+			tabs: vec![
+				Tab { num: 1, field: first.clone() },
+				Tab { num: 2, field: Rc::new(RefCell::new(Placeholder::new(vec![]))) },
+				Tab { num: 1, field: mirror.clone() },
+				Tab { num: 1, field: choice.clone() }
+			],
+			program_filled_text: vec![],
+			references: vec![]
+		};
+		let groups = snip.mirror_groups();
+		assert_eq!(groups[&1].len(), 3);
+		assert_eq!(groups[&2].len(), 1);
+		snip.propagate_mirror(1, vec![Segment::Text(String::from("zakhar"))]);
+		assert_eq!(first.borrow().to_string(), "zakhar");
+		assert_eq!(mirror.borrow().to_string(), "zakhar");
+		assert_eq!(choice.borrow().to_string(), "zakhar");
+		snip.propagate_mirror(1, vec![Segment::Text(String::from("nobody"))]);
+		assert_eq!(mirror.borrow().to_string(), "nobody");
+		assert_eq!(choice.borrow().to_string(), "zakhar");
+	}
+	#[test]
+	fn immutable_programic_cast() {
+		let mut code = Code::new("echo cast");
+		code.evaluate();
+		let programic: Rc<RefCell<dyn Programic>> = Rc::new(RefCell::new(code));
+		let programic = &*programic.borrow();
+		let code: &Code = cast_programic(programic).unwrap();
+		assert_eq!(code.indentifier(), "echo cast");
+		assert_eq!(code.output, "cast\n");
+		assert!(cast_programic::<Variable>(programic).is_none());
+	}
+	#[test]
+	fn mutable_field_casts() {
+		let field: Rc<RefCell<dyn Field>> = Rc::new(RefCell::new(Placeholder::new(vec![])));
+		{
+			let field = &mut *field.borrow_mut();
+			let placeholder: &mut Placeholder = cast_mut_field(field).unwrap();
+			placeholder.set_value(vec![Segment::Text(String::from("typed"))]);
+			assert!(cast_mut_field::<Choice>(field).is_none());
+		}
+		assert_eq!(field.borrow().to_string(), "typed");
+		let interactive: Rc<RefCell<dyn InteractiveSegment>> = Rc::new(RefCell::new(Choice::new(vec![
+			vec![Segment::Text(String::from("a"))],
+			vec![Segment::Text(String::from("b"))]
+		])));
+		{
+			let interactive = &mut *interactive.borrow_mut();
+			let choice: &mut Choice = cast_mut_interactive_segment(interactive).unwrap();
+			choice.next();
+			assert!(cast_mut_interactive_segment::<Placeholder>(interactive).is_none());
+		}
+		assert_eq!(interactive.borrow().to_string(), "b");
+	}
+	#[test]
+	fn evaluate_everything() {
+		let var = Rc::new(RefCell::new(Variable::with_resolver("GREET", |_| String::from("hi"))));
+		let code = Rc::new(RefCell::new(Code::new("echo there")));
+		let nested_code = Rc::new(RefCell::new(Code::new("exit 1")));
+		let mut snip = Snippet {
+			body: vec![
+				Segment::Interactive(var.clone()),
+				Segment::Text(String::from(" ")),
+				Segment::Interactive(Rc::new(RefCell::new(Placeholder::new(vec![
+					Segment::Interactive(code.clone()),
+					Segment::Interactive(nested_code.clone())
+				]))))
+			],
+			tabs: vec![],
+			program_filled_text: vec![var.clone()],
+			references: vec![]
+		};
+		let errors = snip.evaluate_all_with(CodePolicy::Run).unwrap_err();
+		assert_eq!(errors.len(), 1);
+		assert!(matches!(errors[0], SnippetError::ScriptFailed { code: Some(1), .. }));
+		assert_eq!(snip.render(), "hi there\n");
+		nested_code.borrow_mut().code_to_run = String::from("true");
+		assert_eq!(snip.evaluate_all_with(CodePolicy::Run), Ok(()));
+	}
+	#[test]
+	fn display_snippet() {
+		let mut code = Code::new("printf world");
+		code.evaluate();
+		let snip = Snippet {
+			body: vec![
+				Segment::Text(String::from("hello ")),
+				Segment::Interactive(Rc::new(RefCell::new(Choice::try_new(1, vec![
+					vec![Segment::Text(String::from("big "))],
+					vec![Segment::Text(String::from("small "))]
+				]).unwrap()))),
+				Segment::Interactive(Rc::new(RefCell::new(code)))
+			],
+			tabs: vec![],
+			program_filled_text: vec![],
+			references: vec![]
+		};
+		assert_eq!(format!("{}", snip.display()), "hello small world");
+		assert_eq!(snip.display().to_string(), snip.render());
+	}
+	#[test]
+	fn tabstop_counts() {
+		let tab = |num| Tab {
+			num,
+			field: Rc::new(RefCell::new(Placeholder::new(vec![]))) as Rc<RefCell<dyn Field>>
+		};
+		let mut snip = Snippet {
+			body: vec![],
+			tabs: vec![tab(1), tab(3), tab(1), tab(0)],
+			program_filled_text: vec![],
+			references: vec![]
+		};
+		assert_eq!(snip.count_tabstops(), 3);
+		assert_eq!(snip.max_tab_num(), Some(3));
+		snip.tabs.retain(|tab| tab.num == 0);
+		assert_eq!(snip.count_tabstops(), 1);
+		assert_eq!(snip.max_tab_num(), None);
+	}
+	#[test]
+	fn validation_issues() {
+		let placeholder = || Rc::new(RefCell::new(Placeholder::new(vec![]))) as Rc<RefCell<dyn Field>>;
+		let mut snip = Snippet {
+			body: vec![],
+			tabs: vec![
+				Tab { num: 1, field: placeholder() },
+				Tab { num: 2, field: placeholder() },
+				Tab { num: 0, field: placeholder() }
+			],
+			program_filled_text: vec![],
+			references: vec![]
+		};
+		assert_eq!(snip.validate(), Ok(()));
+		snip.tabs.push(Tab { num: 4, field: placeholder() });
+		let issues = snip.validate().unwrap_err();
+		assert_eq!(issues, [ValidationIssue::NonContiguousTabs(vec![3])]);
+		assert_eq!(issues[0].severity(), Severity::Warning);
+		snip.tabs.push(Tab { num: 3, field: Rc::new(RefCell::new(Choice {
+			selected: 2,
+			choices: vec![vec![]],
+			tab_num: None,
+			filter: String::new()
+		})) });
+		snip.tabs.push(Tab { num: 1, field: Rc::new(RefCell::new(Choice::new(vec![vec![]]))) });
+		snip.body.push(Segment::Interactive(Rc::new(RefCell::new(Choice::new(vec![])))));
+		let issues = snip.validate().unwrap_err();
+		assert_eq!(issues, [
+			ValidationIssue::ConflictingMirrors(1),
+			ValidationIssue::ChoiceIndexOutOfRange { tab: Some(3), index: 2, len: 1 },
+			ValidationIssue::EmptyChoice(None)
+		]);
+		assert_eq!(issues[1].severity(), Severity::Error);
+		assert_eq!(issues[2].severity(), Severity::Error);
+	}
+	#[test]
+	fn trim_nested_empty_tabs() {
+		let nested_empty: Rc<RefCell<dyn Field>> = Rc::new(RefCell::new(Placeholder::new(vec![
+			Segment::Interactive(Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::new())]))))
+		])));
+		let empty_choice: Rc<RefCell<dyn Field>> = Rc::new(RefCell::new(Choice::new(vec![
+			vec![],
+			vec![Segment::Text(String::from("not selected"))]
+		])));
+		let kept: Rc<RefCell<dyn Field>> = Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from("kept"))])));
+		let mut snip = Snippet {
+			body: vec![],
+			tabs: vec![
+				Tab { num: 1, field: Rc::clone(&nested_empty) },
+				Tab { num: 2, field: Rc::clone(&empty_choice) },
+				Tab { num: 3, field: Rc::clone(&kept) }
+			],
+			program_filled_text: vec![],
+			references: vec![]
+		};
+		assert_eq!(Rc::strong_count(&nested_empty), 2);
+		snip.trim_empty_tabs_deep();
+		assert_eq!(snip.tabs.len(), 1);
+		assert_eq!(snip.tabs[0].num, 3);
+		assert_eq!(Rc::strong_count(&nested_empty), 1);
+		assert_eq!(Rc::strong_count(&empty_choice), 1);
+		assert_eq!(Rc::strong_count(&kept), 2);
+	}
+	#[test]
+	fn segment_emptiness_and_length() {
+		let text = Segment::Text(String::from("añb"));
+		assert!(!text.is_empty());
+		assert_eq!(text.rendered_len(), 3);
+		assert!(Segment::Text(String::new()).is_empty());
+		let placeholder = Segment::Interactive(Rc::new(RefCell::new(Placeholder::new(vec![
+			Segment::Text(String::from("ab")),
+			Segment::Interactive(Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from("c"))]))))
+		]))));
+		assert!(!placeholder.is_empty());
+		assert_eq!(placeholder.rendered_len(), 3);
+		let choice = Segment::Interactive(Rc::new(RefCell::new(Choice::new(vec![vec![], vec![Segment::Text(String::from("x"))]]))));
+		assert!(choice.is_empty());
+		assert_eq!(choice.rendered_len(), 0);
+		let var = Rc::new(RefCell::new(Variable::with_resolver("V", |_| String::from("value"))));
+		let variable = Segment::Interactive(var.clone());
+		assert!(variable.is_empty());
+		var.borrow_mut().evaluate();
+		assert_eq!(variable.rendered_len(), 5);
+		let code = Segment::Interactive(Rc::new(RefCell::new(Code::new("echo unevaluated"))));
+		assert!(code.is_empty());
+		let reference = Segment::Reference(Rc::new(RefCell::new(Reference::Text(String::from("key"), String::from("v")))));
+		assert_eq!(reference.is_empty(), reference.to_string().is_empty());
+		assert_eq!(reference.rendered_len(), reference.to_string().chars().count());
+	}
+	#[test]
+	#[should_panic]
+	fn downcast_borrowed_pointer() {
+		let placeholder: Rc<RefCell<dyn InteractiveSegment>> = Rc::new(RefCell::new(Placeholder::new(vec![])));
+		let _borrowed = placeholder.borrow_mut();
+		let _ = downcast_rc::<Placeholder>(Rc::clone(&placeholder));
+	}
+	#[test]
+	fn downcast_shared_pointer() {
+		let placeholder: Rc<RefCell<dyn InteractiveSegment>> = Rc::new(RefCell::new(Placeholder::new(vec![])));
+		let segment = Segment::Interactive(Rc::clone(&placeholder));
+		let placeholder = downcast_rc::<Choice>(placeholder).unwrap_err();
+		let placeholder = downcast_rc::<Placeholder>(placeholder).unwrap();
+		let field: Rc<RefCell<dyn Field>> = placeholder.clone();
+		placeholder.borrow_mut().set_value(vec![Segment::Text(String::from("shared"))]);
+		assert_eq!(segment.to_string(), "shared");
+		assert_eq!(field.borrow().to_string(), "shared");
+		assert_eq!(Rc::strong_count(&placeholder), 3);
+	}
+	#[test]
+	fn vscode_body_export() {
+		let name = Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from("name"))])));
+		let empty = Rc::new(RefCell::new(Placeholder::new(vec![])));
+		let nested = Rc::new(RefCell::new(Placeholder::new(vec![
+			Segment::Text(String::from("a ")),
+			Segment::Interactive(name.clone())
+		])));
+		let choice = Rc::new(RefCell::new(Choice::new(vec![
+			vec![Segment::Text(String::from("one"))],
+			vec![Segment::Text(String::from("t,w|o"))]
+		])));
+		let mut var = Variable::new("TM_FILENAME");
+		var.transform = Some(VariableTransform::Upcase);
+		let snip = Snippet {
+			body: vec![
+				Segment::Text(String::from("cost: $5 {x} \\ ")),
+				Segment::Interactive(nested.clone()),
+				Segment::Text(String::from(" ")),
+				Segment::Interactive(choice.clone()),
+				Segment::Interactive(Rc::new(RefCell::new(Variable::new("CLIPBOARD")))),
+				Segment::Interactive(Rc::new(RefCell::new(var))),
+				Segment::Interactive(Rc::new(RefCell::new(Code::new("echo `date`")))),
+				Segment::Interactive(Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from("plain"))])))),
+				Segment::Interactive(empty.clone())
+			],
+			tabs: vec![
+				Tab { num: 1, field: nested },
+				Tab { num: 2, field: name },
+				Tab { num: 3, field: choice },
+				Tab { num: 0, field: empty }
+			],
+			program_filled_text: vec![],
+			references: vec![]
+		};
+		let body = snip.to_vscode_body();
+		println!("{}", body);
+		assert_eq!(body, "cost: \\$5 {x\\} \\\\ ${1:a ${2:name}} ${3|one,t\\,w\\|o|}${CLIPBOARD}${TM_FILENAME/(.*)/${1:/upcase}/}`echo \\`date\\``plain$0");
+	}
+	#[test]
+	fn lsp_snippet_export() {
+		let placeholder = Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from("{foo}"))])));
+		let choice = Rc::new(RefCell::new(Choice::new(vec![
+			vec![Segment::Text(String::from("a"))],
+			vec![Segment::Text(String::from("b|c"))]
+		])));
+		let var = Rc::new(RefCell::new(Variable::with_resolver("PRICE", |_| String::from("$3"))));
+		var.borrow_mut().evaluate();
+		let mut code = Code::new("echo unused");
+		code.output = String::from("out");
+		let final_tab = Rc::new(RefCell::new(Placeholder::new(vec![])));
+		let snip = Snippet {
+			body: vec![
+				Segment::Interactive(placeholder.clone()),
+				Segment::Text(String::from(" ")),
+				Segment::Interactive(choice.clone()),
+				Segment::Text(String::from(" ")),
+				Segment::Interactive(var),
+				Segment::Interactive(Rc::new(RefCell::new(code))),
+				Segment::Interactive(final_tab.clone())
+			],
+			tabs: vec![
+				Tab { num: 1, field: placeholder },
+				Tab { num: 2, field: choice },
+				Tab { num: 0, field: final_tab }
+			],
+			program_filled_text: vec![],
+			references: vec![]
+		};
+		let insert_text = snip.to_lsp_snippet();
+		println!("{}", insert_text);
+		assert_eq!(insert_text, "${1:{foo\\}} ${2|a,b\\|c|} \\$3out$0");
+	}
+	#[test]
+	fn textmate_body_export() {
+		let placeholder = SnippetBuilder::new()
+		.text("fn ")
+		.placeholder(1, vec![Segment::text("name")])
+		.text("() `not code` {}")
+		.build();
+		let body = placeholder.to_textmate_body();
+		println!("{}", body);
+		assert_eq!(body, "fn ${1:name}() \\`not code\\` {\\}");
+		let code = SnippetBuilder::new()
+		.text("// ")
+		.code("date +`echo %Y`")
+		.build();
+		assert_eq!(code.to_textmate_body(), "// `date +\\`echo %Y\\``");
+		let mut var = Variable::new("TM_FILENAME");
+		var.transform = Some(VariableTransform::Upcase);
+		let mut camel = Variable::new("TM_CURRENT_WORD");
+		camel.transform = Some(VariableTransform::CamelCase);
+		let transformed = SnippetBuilder::new()
+		.segment(Segment::from(var))
+		.text(" ")
+		.segment(Segment::from(camel))
+		.build();
+		let body = transformed.to_textmate_body();
+		println!("{}", body);
+		assert_eq!(body, "${TM_FILENAME/.+/\\U$0/} ${TM_CURRENT_WORD/(?:^[^[:alnum:]]*([[:alnum:]]+)|([[:alnum:]]+))[^[:alnum:]]*/(?1:\\L$1\\E)(?2:\\u$2)/g}");
+	}
+	#[test]
+	fn code_on_worker_thread() {
+		fn assert_send_sync<T: Send + Sync>() {}
+		assert_send_sync::<Code>();
+		assert_send_sync::<CodeJobs>();
+		let shared = Rc::new(RefCell::new(Code::new("echo shared")));
+		let mut snip = Snippet {
+			body: vec![
+				Segment::Interactive(Rc::new(RefCell::new(Placeholder::new(vec![
+					Segment::Interactive(Rc::new(RefCell::new(Code::new("echo nested"))))
+				])))),
+				Segment::Interactive(shared.clone())
+			],
+			tabs: vec![],
+			program_filled_text: vec![shared],
+			references: vec![]
+		};
+		let mut jobs = snip.code_jobs();
+		assert_eq!(jobs.0.len(), 2);
+		let jobs = thread::spawn(move || {
+			jobs.evaluate().unwrap();
+			jobs
+		}).join().unwrap();
+		snip.apply_code_jobs(jobs);
+		println!("{}", snip.render());
+		assert_eq!(snip.render(), "nested\nshared\n");
+	}
+	#[test]
+	fn structural_equality() {
+		let build = |nested: &str| Placeholder::new(vec![
+			Segment::Text(String::from("a")),
+			Segment::Interactive(Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from(nested))])))),
+			Segment::Interactive(Rc::new(RefCell::new(Choice::new(vec![vec![Segment::Text(String::from("x"))]])))),
+			Segment::Interactive(Rc::new(RefCell::new(Code::new("echo"))))
+		]);
+		assert_eq!(build("b"), build("b"));
+		assert_ne!(build("b"), build("c"));
+		let mut typed = build("b");
+		typed.set_value(vec![]);
+		assert_ne!(typed, build("b"));
+		let mut choice = Choice::new(vec![vec![], vec![Segment::Text(String::from("y"))]]);
+		assert_eq!(choice, choice.clone());
+		let other = choice.clone();
+		choice.next();
+		assert_ne!(choice, other);
+		let mut code = Code::new("echo hi");
+		code.runner = ScriptRunner::Bash;
+		assert_eq!(code, Code::new("echo hi"));
+		code.output = String::from("hi");
+		assert_ne!(code, Code::new("echo hi"));
+	}
+	#[test]
+	fn custom_variable_resolver() {
+		struct Editor {
+			filename: String
+		}
+		impl VariableResolver for Editor {
+			fn resolve(&self, name: &str) -> Option<String> {
+				match name {
+					"TM_FILENAME" => Some(self.filename.clone()),
+					_ => None
 				}
 			}
-//			segs[0] = Segment::Text(String::from("dud!"));
 		}
-		let field_rc = tab.field;
-		println!("Reference!");
-		let field = &*field_rc.borrow();
-		let placeholder: &Placeholder = cast_field(field).unwrap();
-		println!("Just making sure! {}", placeholder);
-		println!("Just making sure again! {}", placeholder);
+		let editor: Rc<dyn VariableResolver> = Rc::new(Editor { filename: String::from("main.rs") });
+		let mut var = Variable::resolved_by("TM_FILENAME", Rc::clone(&editor));
+		var.transform = Some(VariableTransform::Upcase);
+		assert_eq!(var.try_evaluate(), Ok(()));
+		assert_eq!(var.value, "MAIN.RS");
+		let mut copy = var.clone();
+		copy.transform = None;
+		copy.evaluate();
+		assert_eq!(copy.value, "main.rs");
+		let mut unknown = Variable::resolved_by("TM_SELECTED_TEXT", editor);
+		assert_eq!(unknown.try_evaluate(), Err(SnippetError::VariableNotFound(String::from("TM_SELECTED_TEXT"))));
+		let mut env = Variable::new("TM_FILENAME");
+		assert!(env.try_evaluate_with(&Editor { filename: String::from("lib.rs") }).is_ok());
+		assert_eq!(env.value, "lib.rs");
+		assert_eq!(EnvResolver.resolve("SNIPPET_SEGMENT_TYPES_UNSET_VARIABLE"), None);
 	}
 	#[test]
-	fn partial_eq_test() {
-		let reference = Reference::Text(String::from("Greetings"), String::from("Hi"));
-		let reference = Rc::new(RefCell::new(reference));
-		let segment = Segment::Reference(Rc::clone(&reference));
-		assert!(*reference.borrow() == segment, "Reference not equal to segment!");
-		println!("{}", *reference.borrow() == segment);
+	fn variable_default() {
+		let placeholder = Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from("nothing selected"))])));
+		let inner = Rc::new(RefCell::new(Variable::with_resolver("USER", |_| String::from("zakhar"))));
+		let selection = Rc::new(RefCell::new(Variable::with_resolver("TM_SELECTED_TEXT", |_| String::new())));
+		selection.borrow_mut().default = vec![
+			Segment::Interactive(placeholder.clone()),
+			Segment::Text(String::from(" by ")),
+			Segment::Interactive(inner.clone())
+		];
+		let mut snip = Snippet {
+			body: vec![Segment::Interactive(selection.clone())],
+			tabs: vec![Tab { num: 1, field: placeholder }],
+			program_filled_text: vec![],
+			references: vec![]
+		};
+		assert_eq!(snip.evaluate_all(), Ok(()));
+		println!("{}", snip.render());
+		assert_eq!(snip.render(), "nothing selected by zakhar");
+		assert_eq!(snip.to_vscode_body(), "${TM_SELECTED_TEXT:${1:nothing selected} by ${USER}}");
+		selection.borrow_mut().get_from_client = Some(Box::new(|_| String::from("selected")));
+		assert_eq!(snip.evaluate_all(), Ok(()));
+		assert_eq!(snip.render(), "selected");
+		assert_eq!(snip.walk_segments().count(), 1);
 	}
 	#[test]
-	fn trim_empty_test() {
-		let field: Rc<RefCell<dyn Field>> = Rc::new(RefCell::new(Placeholder(
-			vec![Segment::Text(
-				String::from("Hello ")
-			)]
-		)));
+	fn cached_code_runs_once() {
+		let log = std::env::temp_dir().join(format!("snippet-segment-types-cache-{}", std::process::id()));
+		let _ = std::fs::remove_file(&log);
+		let mut code = Code::new(format!("echo ran >> '{}'; echo out", log.display()));
+		code.cache = true;
+		code.evaluate();
+		code.output = String::from("edited");
+		code.evaluate();
+		code.evaluate();
+		assert_eq!(code.output, "edited");
+		assert_eq!(std::fs::read_to_string(&log).unwrap(), "ran\n");
+		code.invalidate_cache();
+		code.evaluate();
+		assert_eq!(code.output, "out\n");
+		code.code_to_run.push_str(" again");
+		code.evaluate();
+		assert_eq!(code.output, "out again\n");
+		println!("{}", std::fs::read_to_string(&log).unwrap());
+		assert_eq!(std::fs::read_to_string(&log).unwrap(), "ran\nran\nran\n");
+		let _ = std::fs::remove_file(&log);
+	}
+	#[cfg(feature = "tokio")]
+	#[tokio::test]
+	async fn async_code() {
+		let mut code = Code::new("echo hi");
+		assert_eq!(code.evaluate_async().await, Ok(()));
+		assert_eq!(code.output, "hi\n");
+		assert_eq!(code.last_exit_code, Some(0));
+		let mut slow = Code::new("sleep 5");
+		slow.timeout = Some(Duration::from_millis(100));
+		assert_eq!(slow.evaluate_async().await, Err(SnippetError::Timeout));
+	}
+	#[test]
+	fn collect_variables_and_code() {
+		let shared = Rc::new(RefCell::new(Variable::new("SHARED")));
+		let code = Rc::new(RefCell::new(Code::new("echo")));
+		let field = Rc::new(RefCell::new(Placeholder::new(vec![
+			Segment::Interactive(Rc::new(RefCell::new(Variable::new("IN_TAB")))),
+			Segment::Interactive(shared.clone())
+		])));
 		let mut snip = Snippet {
-			body: vec![],
-			tabs: vec![Tab {
-				num: 1,
-				field: Rc::clone(&field)
-			}],
+			body: vec![
+				Segment::Interactive(shared.clone()),
+				Segment::Interactive(Rc::new(RefCell::new(Choice::new(vec![
+					vec![Segment::Interactive(Rc::new(RefCell::new(Variable::new("IN_CHOICE"))))],
+					vec![Segment::Interactive(code.clone())]
+				]))))
+			],
+			tabs: vec![Tab { num: 1, field }],
+			program_filled_text: vec![shared.clone(), code.clone()],
+			references: vec![]
+		};
+		let names: Vec<String> = snip.collect_variables().iter().map(|var| var.borrow().name.clone()).collect();
+		println!("{:?}", names);
+		assert_eq!(names, vec!["SHARED", "IN_CHOICE", "IN_TAB"]);
+		assert!(Rc::ptr_eq(&snip.collect_variables()[0], &shared));
+		snip.program_filled_text.clear();
+		let codes = snip.collect_code();
+		assert_eq!(codes.len(), 1);
+		assert!(Rc::ptr_eq(&codes[0], &code));
+	}
+	#[test]
+	fn variable_interpolation() {
+		struct Values(HashMap<&'static str, &'static str>);
+		impl VariableResolver for Values {
+			fn resolve(&self, name: &str) -> Option<String> {
+				self.0.get(name).map(|value| String::from(*value))
+			}
+		}
+		let values: Rc<dyn VariableResolver> = Rc::new(Values(HashMap::from([
+			("HOME", "/home/zakhar"),
+			("PROJECTS", "$HOME/projects/${NAME}_$ $UNKNOWN"),
+			("NAME", "snippets"),
+			("A", "a $B"),
+			("B", "b $A")
+		])));
+		let mut var = Variable::resolved_by("PROJECTS", values.clone());
+		var.interpolate = true;
+		assert_eq!(var.try_evaluate(), Ok(()));
+		println!("{}", var.value);
+		assert_eq!(var.value, "/home/zakhar/projects/snippets_$ $UNKNOWN");
+		var.interpolate = false;
+		var.evaluate();
+		assert_eq!(var.value, "$HOME/projects/${NAME}_$ $UNKNOWN");
+		let mut cycle = Variable::resolved_by("A", values);
+		cycle.interpolate = true;
+		assert_eq!(cycle.try_evaluate(), Err(SnippetError::InterpolationCycle(String::from("A"))));
+		assert_eq!(cycle.value, "");
+		let mut client = Variable::with_resolver("GREETING", |name| if name == "GREETING" {
+			String::from("hi $WHO")
+		} else {
+			String::from("there")
+		});
+		client.interpolate = true;
+		client.evaluate();
+		assert_eq!(client.value, "hi there");
+	}
+	#[test]
+	fn computed_reference_follows_code() {
+		let code = Rc::new(RefCell::new(Code::new("echo -n first")));
+		let reference = || Segment::Interactive(Rc::new(RefCell::new(ComputedReference::new("out", code.clone()))));
+		let mut snip = Snippet {
+			body: vec![reference(), Segment::Text(String::from(" and ")), reference()],
+			tabs: vec![],
+			program_filled_text: vec![code.clone()],
+			references: vec![]
+		};
+		assert_eq!(snip.evaluate_all_with(CodePolicy::Run), Ok(()));
+		assert_eq!(snip.render(), "first and first");
+		code.borrow_mut().code_to_run = String::from("echo -n second");
+		assert_eq!(snip.evaluate_all_with(CodePolicy::Run), Ok(()));
+		println!("{}", snip.render());
+		assert_eq!(snip.render(), "second and second");
+		assert!(segments_eq(&snip.body[..1], &snip.body[2..]));
+	}
+	#[test]
+	fn snippet_builder() {
+		let mut snippet = SnippetBuilder::new()
+		.placeholder(1, vec![
+			Segment::Text(String::from("hello")),
+			Segment::Text(String::from("there!"))
+		])
+		.text(" ")
+		.code("greet=hi echo yes")
+		.reference("Greetings", "Hi")
+		.build();
+		assert_eq!(snippet.tabs.len(), 1);
+		assert_eq!(snippet.tabs[0].num, 1);
+		{
+			let field = &*snippet.tabs[0].field.borrow();
+			let placeholder: &Placeholder = cast_field(field).unwrap();
+			println!("Just making sure! {}", placeholder);
+			assert_eq!(placeholder.segments().len(), 2);
+		}
+		assert_eq!(snippet.program_filled_text.len(), 1);
+		assert_eq!(snippet.references.len(), 1);
+		assert_eq!(snippet.evaluate_all_with(CodePolicy::Run), Ok(()));
+		{
+			let interactive = &mut *snippet.program_filled_text[0].borrow_mut();
+			let code: &mut Code = cast_mut_programic(interactive).unwrap();
+			assert_eq!(code.output, "yes\n");
+		}
+		assert_eq!(snippet.render(), format!("hellothere! yes\n{}", snippet.references[0].borrow()));
+	}
+	#[test]
+	fn segment_kinds() {
+		#[derive(Debug)]
+		struct Custom;
+		impl fmt::Display for Custom {
+			fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+				"custom".fmt(f)
+			}
+		}
+		impl InteractiveSegment for Custom {
+			fn get_type(&self) -> &str {
+				"placeholder"
+			}
+		}
+		let code: Rc<RefCell<dyn Programic>> = Rc::new(RefCell::new(Code::new("echo")));
+		let segments: Vec<Rc<RefCell<dyn InteractiveSegment>>> = vec![
+			Rc::new(RefCell::new(Placeholder::new(vec![]))),
+			Rc::new(RefCell::new(Choice::new(vec![]))),
+			Rc::new(RefCell::new(Variable::new("V"))),
+			Rc::new(RefCell::new(ComputedReference::new("c", code.clone()))),
+			Rc::new(RefCell::new(Custom))
+		];
+		let expected = [
+			SegmentType::Placeholder,
+			SegmentType::Choice,
+			SegmentType::Variable,
+			SegmentType::ComputedReference,
+			SegmentType::Other("placeholder")
+		];
+		for (segment, expected) in segments.iter().zip(expected) {
+			let segment = segment.borrow();
+			println!("{:?}", segment.kind());
+			assert_eq!(segment.kind(), expected);
+			assert_eq!(segment.kind().as_str(), segment.get_type());
+		}
+		let code: &dyn InteractiveSegment = &*code.borrow();
+		assert_eq!(code.kind(), SegmentType::Code);
+	}
+	#[test]
+	fn self_nested_display_terminates() {
+		let outer = Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from("a"))])));
+		let inner = Rc::new(RefCell::new(Choice::new(vec![vec![
+			Segment::Text(String::from("b")),
+			Segment::Interactive(outer.clone())
+		]])));
+		outer.borrow_mut().default.push(Segment::Interactive(inner.clone()));
+		let rendered = outer.borrow().to_string();
+		println!("{}", rendered);
+		assert_eq!(rendered, "ab…");
+		assert_eq!(inner.borrow().to_string(), "ba…");
+		let snip = Snippet {
+			body: vec![Segment::Interactive(outer.clone()), Segment::Interactive(outer.clone())],
+			tabs: vec![],
 			program_filled_text: vec![],
-			references: vec![],
+			references: vec![]
 		};
-		println!("tab 1 count: {}", Rc::strong_count(&snip.tabs[0].field));
-		snip.trim_empty_tabs();
-		println!("tab 1 count: {}", Rc::strong_count(&snip.tabs[0].field));
-		println!("number of tabs: {}", snip.tabs.len());
+		assert_eq!(snip.render(), "ab…ab…");
+		outer.borrow_mut().default.clear();
 	}
 	#[test]
-	fn test_debug_impl() {
-		let mut var = Variable {
-			name: String::from("test"),
-			value: String::new(),
-			get_from_client: None
+	fn deep_clone_snippet() {
+		let mirrored = Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from("name"))])));
+		let code = Rc::new(RefCell::new(Code::new("echo")));
+		let original = Snippet {
+			body: vec![
+				Segment::Interactive(mirrored.clone()),
+				Segment::Interactive(Rc::new(RefCell::new(ComputedReference::new("out", code.clone())))),
+				Segment::Interactive(code.clone())
+			],
+			tabs: vec![
+				Tab { num: 1, field: mirrored.clone() },
+				Tab { num: 1, field: mirrored.clone() }
+			],
+			program_filled_text: vec![code.clone()],
+			references: vec![]
 		};
+		let copy = original.clone_deep();
+		assert!(!std::ptr::addr_eq(Rc::as_ptr(&copy.tabs[0].field), Rc::as_ptr(&mirrored)));
+		assert!(std::ptr::addr_eq(Rc::as_ptr(&copy.tabs[0].field), Rc::as_ptr(&copy.tabs[1].field)));
+		if let Segment::Interactive(field) = &copy.body[0] {
+			assert!(std::ptr::addr_eq(Rc::as_ptr(field), Rc::as_ptr(&copy.tabs[0].field)));
+			cast_mut_interactive_segment::<Placeholder>(&mut *field.borrow_mut()).unwrap().set_value(vec![Segment::Text(String::from("changed"))]);
+		}
+		assert_eq!(copy.tabs[1].field.borrow().to_string(), "changed");
+		assert_eq!(original.render(), "name");
+		if let Segment::Interactive(copied_code) = &copy.body[2] {
+			assert!(std::ptr::addr_eq(Rc::as_ptr(copied_code), Rc::as_ptr(&copy.program_filled_text[0])));
+			assert!(!std::ptr::addr_eq(Rc::as_ptr(copied_code), Rc::as_ptr(&code)));
+		}
+		copy.program_filled_text[0].borrow_mut().evaluate();
+		println!("{}", copy.render());
+		assert_eq!(copy.render(), "changed\n\n");
+		assert_eq!(original.render(), "name");
+	}
+	#[test]
+	fn choice_option_strings() {
+		let var = Rc::new(RefCell::new(Variable::with_resolver("WHO", |_| String::from("world"))));
+		var.borrow_mut().evaluate();
+		let mut choice = Choice::new(vec![
+			vec![Segment::Text(String::from("one"))],
+			vec![Segment::Text(String::from("hello ")), Segment::Interactive(var)],
+			vec![Segment::Text(String::from("three"))]
+		]);
+		choice.next();
+		println!("{:?}", choice.option_strings());
+		assert_eq!(choice.option_strings(), vec!["one", "hello world", "three"]);
+		assert_eq!(choice.selected_string().as_deref(), Some("hello world"));
+		choice.selected = 3;
+		assert_eq!(choice.selected_string(), None);
+	}
+	#[test]
+	fn fields_know_their_tab() {
+		let snip = SnippetBuilder::new()
+		.placeholder(2, vec![Segment::Text(String::from("name"))])
+		.choice(1, vec![vec![Segment::Text(String::from("a"))]])
+		.build();
+		let nums: Vec<Option<usize>> = snip.tabs.iter().map(|tab| tab.field.borrow().tab_num()).collect();
+		println!("{:?}", nums);
+		assert_eq!(nums, vec![Some(2), Some(1)]);
+		if let Segment::Interactive(interactive) = &snip.body[0] {
+			assert_eq!(cast_interactive_segment::<Placeholder>(&*interactive.borrow()).unwrap().tab_num, Some(2));
+		}
+		assert_eq!(Placeholder::new(vec![]).tab_num, None);
+	}
+	#[test]
+	fn caret_at_final_tab() {
+		let mut snip = SnippetBuilder::new()
+		.text("abc")
+		.placeholder(0, vec![])
+		.text("déf")
+		.build();
+		assert_eq!(snip.caret_offset(), 3);
+		snip.tabs.clear();
+		println!("{}", snip.render());
+		assert_eq!(snip.caret_offset(), 6);
+		let nested = SnippetBuilder::new()
+		.text("ab")
+		.segment(Segment::Interactive(Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from("cd"))])))))
+		.placeholder(1, vec![Segment::Text(String::from("x"))])
+		.placeholder(0, vec![])
+		.build();
+		assert_eq!(nested.caret_offset(), 5);
+	}
+	#[test]
+	fn tab_selection_ranges() {
+		let name = Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from("name"))])));
+		let mut snip = SnippetBuilder::new()
+		.text("fn ")
+		.placeholder(1, vec![Segment::Text(String::from("main"))])
+		.text("(")
+		.placeholder(2, vec![Segment::Text(String::from("x: ")), Segment::Interactive(name.clone())])
+		.text(") ")
+		.segment(Segment::Interactive(name.clone()))
+		.build();
+		snip.tabs.push(Tab { num: 3, field: name });
+		let ranges = snip.tab_ranges();
+		println!("{} {:?}", snip.render(), ranges);
+		assert_eq!(ranges, vec![(1, 3..7), (2, 8..15), (3, 11..15), (3, 17..21)]);
+		let rendered: Vec<char> = snip.render().chars().collect();
+		assert_eq!(rendered[8..15].iter().collect::<String>(), "x: name");
+	}
+	#[test]
+	fn placeholder_from_iterator() {
+		let placeholder: Placeholder = ["a", "b", "c"].iter().map(|text| Segment::Text(text.to_uppercase())).collect();
+		assert_eq!(placeholder.to_string(), "ABC");
+		assert_eq!((&placeholder).into_iter().count(), 3);
+		let lower: Vec<String> = placeholder.into_iter().map(|seg| seg.to_string().to_lowercase()).collect();
+		println!("{:?}", lower);
+		assert_eq!(lower, vec!["a", "b", "c"]);
+		let mut typed: Placeholder = std::iter::empty().collect();
+		typed.set_value(vec![Segment::Text(String::from("typed"))]);
+		assert_eq!(typed.into_iter().map(|seg| seg.to_string()).collect::<String>(), "typed");
+	}
+	#[test]
+	#[allow(clippy::mutable_key_type)]
+	fn equal_placeholders_hash_alike() {
+		use std::collections::HashSet;
+		let build = || Placeholder::new(vec![
+			Segment::Text(String::from("a")),
+			Segment::Interactive(Rc::new(RefCell::new(Choice::new(vec![vec![Segment::Text(String::from("b"))]])))),
+			Segment::Interactive(Rc::new(RefCell::new(Variable::with_resolver("V", |_| String::from("v")))))
+		]);
+		let mut set = HashSet::new();
+		set.insert(build());
+		set.insert(build());
+		assert_eq!(set.len(), 1);
+		set.insert(Placeholder::new(vec![Segment::Text(String::from("a"))]));
+		println!("{:?}", set);
+		assert_eq!(set.len(), 2);
+		let codes: HashSet<Code> = [Code::new("echo"), Code::new("echo"), Code::new("true")].into_iter().collect();
+		assert_eq!(codes.len(), 2);
+	}
+	#[test]
+	fn find_tabs_by_number() {
+		let mut snip = SnippetBuilder::new()
+		.placeholder(1, vec![Segment::Text(String::from("one"))])
+		.placeholder(2, vec![Segment::Text(String::from("two"))])
+		.placeholder(2, vec![Segment::Text(String::from("mirror"))])
+		.placeholder(3, vec![Segment::Text(String::from("three"))])
+		.build();
+		assert_eq!(snip.find_tab(2).unwrap().field.borrow().to_string(), "two");
+		assert!(snip.find_tab(4).is_none());
+		let tab = snip.find_tab_mut(2).unwrap();
+		tab.field = Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from("replaced"))])));
+		tab.num = 4;
+		println!("{:?}", snip.find_tab(4));
+		assert_eq!(snip.find_tab(2).unwrap().field.borrow().to_string(), "mirror");
+		assert_eq!(snip.find_tab(4).unwrap().field.borrow().to_string(), "replaced");
+	}
+	#[test]
+	fn code_skipped_unless_allowed() {
+		let code = Rc::new(RefCell::new(Code::new("echo ran")));
+		let mut snip = SnippetBuilder::new()
+		.segment(Segment::Interactive(code.clone()))
+		.variable("SNIPPET_SEGMENT_TYPES_UNSET_VARIABLE")
+		.build();
+		snip.program_filled_text.push(code.clone());
+		let errors = snip.evaluate_all_with(CodePolicy::Skip).unwrap_err();
+		assert_eq!(errors, vec![SnippetError::VariableNotFound(String::from("SNIPPET_SEGMENT_TYPES_UNSET_VARIABLE"))]);
+		assert_eq!(code.borrow().output, "");
+		assert_eq!(code.borrow().last_exit_code, None);
+		println!("would run: {}", code.borrow().dry_run());
+		assert_eq!(code.borrow().dry_run(), "echo ran");
+		assert!(snip.evaluate_all_with(CodePolicy::default()).is_err());
+		assert!(snip.evaluate_all().is_err());
+		assert!(snip.evaluate_code_with_variable_env(CodePolicy::Skip).is_err());
+		assert!(snip.expand_with_resolver(&BuiltinResolver::new(), CodePolicy::Skip).is_err());
+		let placeholder: Rc<RefCell<dyn InteractiveSegment>> = Rc::new(RefCell::new(Placeholder::new(vec![Segment::Interactive(code.clone())])));
+		assert_eq!(placeholder.borrow_mut().try_evaluate(CodePolicy::Skip), Ok(()));
+		assert_eq!(code.borrow().output, "");
+		assert!(snip.evaluate_all_with(CodePolicy::Run).is_err());
+		assert_eq!(code.borrow().output, "ran\n");
+	}
+	#[test]
+	fn evaluation_state() {
+		let mut var = Variable::with_resolver("V", |_| String::new());
+		assert!(!var.is_resolved());
 		var.evaluate();
-		println!("{:#?}", var);
-		let var = Rc::new(RefCell::new(var));
-		let var_clone = Rc::clone(&var);
-		let text = Segment::Text(String::from("Hello! "));
-		println!("{:#?}", text);
+		assert!(var.is_resolved());
+		assert_eq!(var.value, "");
+		let mut missing = Variable::new("SNIPPET_SEGMENT_TYPES_UNSET_VARIABLE");
+		missing.evaluate();
+		assert!(!missing.is_resolved());
+		let mut code = Code::new("true");
+		assert!(!code.is_evaluated());
+		code.evaluate();
+		assert!(code.is_evaluated());
+		code.code_to_run = String::from("false");
+		code.evaluate();
+		println!("{:?}", code);
+		assert!(!code.is_evaluated());
+	}
+	#[test]
+	fn code_sees_snippet_variables() {
+		let code = Rc::new(RefCell::new(Code::new("echo \"$GREET $WHO\"")));
+		code.borrow_mut().env.push((String::from("WHO"), String::from("world")));
+		let mut snip = SnippetBuilder::new()
+		.segment(Segment::Interactive(Rc::new(RefCell::new(Variable::with_resolver("GREET", |_| String::from("hello"))))))
+		.text(": ")
+		.segment(Segment::Interactive(code.clone()))
+		.build();
+		assert_eq!(snip.evaluate_code_with_variable_env(CodePolicy::Run), Ok(()));
+		println!("{}", snip.render());
+		assert_eq!(code.borrow().output, "hello world\n");
+		assert_eq!(snip.render(), "hello: hello world\n");
+	}
+	#[test]
+	fn segment_accessors() {
+		let snip = SnippetBuilder::new()
+		.text("text")
+		.placeholder(1, vec![])
+		.reference("key", "value")
+		.build();
+		let kinds: Vec<(bool, bool, bool)> = snip.body.iter().map(|seg| (seg.is_text(), seg.is_interactive(), seg.is_reference())).collect();
+		println!("{:?}", kinds);
+		assert_eq!(kinds, vec![(true, false, false), (false, true, false), (false, false, true)]);
+		assert_eq!(snip.body[0].as_text(), Some("text"));
+		assert!(snip.body[1].as_text().is_none());
+		assert!(std::ptr::addr_eq(Rc::as_ptr(snip.body[1].as_interactive().unwrap()), Rc::as_ptr(&snip.tabs[0].field)));
+		assert!(Rc::ptr_eq(snip.body[2].as_reference().unwrap(), &snip.references[0]));
+		assert!(snip.body[0].as_reference().is_none());
+	}
+	#[test]
+	fn empty_snippet() {
+		let snip = Snippet::new();
+		assert_eq!(snip.render(), "");
+		println!("{:?}", snip);
+		assert!(snip.body.is_empty() && snip.tabs.is_empty() && snip.program_filled_text.is_empty() && snip.references.is_empty());
+		assert_eq!(SnippetBuilder::default().build().render(), "");
+	}
+	#[test]
+	fn checked_choice_construction() {
+		assert_eq!(Choice::try_new(0, vec![]).unwrap_err(), SnippetError::EmptyChoice);
+		let err = Choice::try_new(2, vec![vec![], vec![]]).unwrap_err();
+		println!("{}", err);
+		assert_eq!(err, SnippetError::ChoiceIndexOutOfRange { index: 2, len: 2 });
+		let choice = Choice::try_new(1, vec![vec![Segment::Text(String::from("a"))], vec![Segment::Text(String::from("b"))]]).unwrap();
+		assert_eq!(choice.to_string(), "b");
+	}
+	#[test]
+	fn replace_tab_field() {
+		let old: Rc<RefCell<dyn Field>> = Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from("name"))])));
+		let mirror: Rc<RefCell<dyn Field>> = Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from("name"))])));
 		let mut snip = Snippet {
-			body: vec![text, Segment::Interactive(var)],
-			tabs: vec![],
-			program_filled_text: vec![var_clone],
-			references: vec![],
+			body: vec![
+				Segment::Text(String::from("Hello ")),
+				Segment::Interactive(Rc::clone(&old) as Rc<RefCell<dyn InteractiveSegment>>),
+				Segment::Text(String::from(", bye ")),
+				Segment::Interactive(Rc::new(RefCell::new(Placeholder::new(vec![
+					Segment::Interactive(Rc::clone(&mirror) as Rc<RefCell<dyn InteractiveSegment>>)
+				]))))
+			],
+			tabs: vec![
+				Tab { num: 1, field: Rc::clone(&old) },
+				Tab { num: 1, field: Rc::clone(&mirror) }
+			],
+			program_filled_text: vec![],
+			references: vec![]
 		};
-		println!("{:#?}", snip);
+		snip.replace_tab_field(1, Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from("zakhar"))]))));
+		println!("{}", snip.render());
+		assert_eq!(snip.render(), "Hello zakhar, bye zakhar");
+		assert_eq!(Rc::strong_count(&old), 1);
+		assert_eq!(Rc::strong_count(&mirror), 1);
+		assert!(!Rc::ptr_eq(&snip.tabs[0].field, &snip.tabs[1].field));
+		assert_eq!(snip.tabs[1].field.borrow().tab_num(), Some(1));
+		assert_eq!(Rc::strong_count(&snip.tabs[0].field), 2);
+	}
+	#[test]
+	fn streamed_code_output() {
+		let mut code = Code::new("printf 'a\\nb\\nc\\n'");
+		let mut lines = vec![];
+		code.evaluate_streaming(|line| {
+			println!("line: {}", line);
+			lines.push(String::from(line));
+		}).unwrap();
+		assert_eq!(lines, ["a", "b", "c"]);
+		assert_eq!(code.output, "a\nb\nc\n");
+		assert!(code.is_evaluated());
+		assert!(Code::new("exit 3").evaluate_streaming(|_| {}).is_err());
+	}
+	#[test]
+	fn segments_from_segment_types() {
+		let placeholder: Segment = Placeholder::new(vec![Segment::Text(String::from("name"))]).into();
+		let choice: Segment = Choice::new(vec![vec![Segment::Text(String::from("a"))]]).into();
+		let variable: Segment = Variable::new("NAME").into();
+		let code: Segment = Code::new("echo hi").into();
+		let reference: Segment = ComputedReference::new("ref", Rc::new(RefCell::new(Code::new("echo hi")))).into();
+		for segment in [&placeholder, &choice, &variable, &code, &reference] {
+			println!("{:?}", segment);
+			assert!(segment.is_interactive());
+		}
+		assert_eq!(placeholder.to_string(), "name");
+		assert_eq!(choice.as_interactive().unwrap().borrow().kind(), SegmentType::Choice);
+		assert_eq!(variable.as_interactive().unwrap().borrow().kind(), SegmentType::Variable);
+		assert_eq!(code.as_interactive().unwrap().borrow().kind(), SegmentType::Code);
+		assert_eq!(reference.as_interactive().unwrap().borrow().kind(), SegmentType::ComputedReference);
+	}
+	#[test]
+	fn renumbered_tabs() {
+		let mut snip = Snippet::new();
+		for num in [3, 7, 3, 0] {
+			snip.tabs.push(Tab {
+				num,
+				field: Rc::new(RefCell::new(Placeholder::new(vec![])))
+			});
+		}
+		snip.renumber_tabs();
+		let nums: Vec<usize> = snip.tabs.iter().map(|tab| tab.num).collect();
+		println!("{:?}", nums);
+		assert_eq!(nums, [1, 2, 1, 0]);
+		assert_eq!(snip.tabs[1].field.borrow().tab_num(), Some(2));
+		assert_eq!(snip.mirror_groups()[&1].len(), 2);
+	}
+	#[test]
+	fn variable_value_override() {
+		let variable = Rc::new(RefCell::new(Variable::with_resolver("LANGUAGE", |_| String::from("resolved"))));
+		variable.borrow_mut().set_value("rust");
+		let mut snip = SnippetBuilder::new().text("lang: ").segment(Segment::Interactive(variable.clone())).build();
+		snip.evaluate_all().unwrap();
+		println!("{}", snip.render());
+		assert_eq!(snip.render(), "lang: rust");
+		assert!(variable.borrow().is_resolved());
+		variable.borrow_mut().force_evaluate().unwrap();
+		assert_eq!(snip.render(), "lang: resolved");
+		snip.evaluate_all().unwrap();
+		assert_eq!(variable.borrow().value, "resolved");
+	}
+	#[test]
+	fn resolve_references() {
+		let reference = Reference::Text(String::from("k"), String::from("v"));
+		println!("{}", reference.resolve());
+		assert_eq!(reference.resolve(), "v");
+		assert_eq!(reference.resolve(), Segment::Reference(Rc::new(RefCell::new(reference))).to_string());
+		let code = Rc::new(RefCell::new(Code::new("echo hi")));
+		let computed = ComputedReference::new("greeting", code.clone());
+		assert_eq!(computed.resolve(), "");
+		code.borrow_mut().try_evaluate().unwrap();
+		assert_eq!(computed.resolve(), "hi\n");
+	}
+	#[test]
+	fn render_diff() {
+		let choice = Rc::new(RefCell::new(Choice::new(vec![
+			vec![Segment::Text(String::from("cat"))],
+			vec![Segment::Text(String::from("horse"))]
+		])));
+		let snip = SnippetBuilder::new().text("my ").segment(Segment::Interactive(choice.clone())).text(" runs").build();
+		let previous = snip.render();
+		assert!(snip.diff_render(&previous).is_empty());
+		choice.borrow_mut().selected = 1;
+		let edits = snip.diff_render(&previous);
+		println!("{:?}", edits);
+		assert_eq!(edits, [(3..6, String::from("horse"))]);
+		let mut patched: Vec<char> = previous.chars().collect();
+		let (range, text) = &edits[0];
+		patched.splice(range.clone(), text.chars());
+		assert_eq!(patched.into_iter().collect::<String>(), snip.render());
+	}
+	#[test]
+	fn active_segments_of_choice() {
+		let inner = Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from("inner"))])));
+		let nested_choice = Rc::new(RefCell::new(Choice::new(vec![vec![Segment::Interactive(inner.clone())]])));
+		let choice = Rc::new(RefCell::new(Choice::new(vec![
+			vec![Segment::Text(String::from("a ")), Segment::Interactive(nested_choice.clone())],
+			vec![Segment::Text(String::from("plain"))]
+		])));
+		let snip = SnippetBuilder::new().segment(Segment::Interactive(choice.clone())).build();
+		let active = choice.borrow().active_interactive_segments();
+		println!("{:?}", active);
+		assert_eq!(active.len(), 2);
+		assert_eq!(Rc::as_ptr(&active[1]) as *const (), Rc::as_ptr(&inner) as *const ());
+		assert_eq!(snip.walk_segments().filter(SegmentExt::is_interactive).count(), 3);
+		assert_eq!(snip.render(), "a inner");
+		choice.borrow_mut().select(1).unwrap();
+		assert!(choice.borrow().active_interactive_segments().is_empty());
+		assert_eq!(snip.walk_segments().filter(SegmentExt::is_interactive).count(), 1);
+		assert_eq!(snip.render(), "plain");
+	}
+	#[test]
+	fn sorted_tabs() {
+		let mut snip = Snippet::new();
+		for num in [2, 0, 1] {
+			snip.tabs.push(Tab {
+				num,
+				field: Rc::new(RefCell::new(Placeholder::new(vec![])))
+			});
+		}
+		snip.sort_tabs();
+		let nums: Vec<usize> = snip.tabs.iter().map(|tab| tab.num).collect();
+		println!("{:?}", nums);
+		assert_eq!(nums, [1, 2, 0]);
+		assert_eq!(cmp_tabs(&snip.tabs[2], &snip.tabs[0]), std::cmp::Ordering::Greater);
+	}
+	#[test]
+	fn code_working_dir() {
+		let dir = std::env::temp_dir().canonicalize().unwrap();
+		let mut code = Code::new("pwd -P");
+		code.working_dir = Some(dir.clone());
+		code.try_evaluate().unwrap();
+		println!("{}", code.output);
+		assert_eq!(code.output.trim_end(), dir.to_str().unwrap());
+	}
+	#[test]
+	fn apply_saved_choices() {
+		let options = |a: &str, b: &str| vec![vec![Segment::Text(String::from(a))], vec![Segment::Text(String::from(b))]];
+		let mut snip = SnippetBuilder::new()
+		.choice(1, options("pub ", ""))
+		.text("fn ")
+		.choice(2, options("get", "set"))
+		.placeholder(3, vec![Segment::Text(String::from("()"))])
+		.build();
+		assert_eq!(snip.render(), "pub fn get()");
+		snip.apply_choices(&[(1, 1), (2, 1)]).unwrap();
+		println!("{}", snip.render());
+		assert_eq!(snip.render(), "fn set()");
+		assert_eq!(snip.apply_choices(&[(2, 0), (2, 5), (3, 0), (9, 0)]), Err(vec![(2, 5), (3, 0), (9, 0)]));
+		assert_eq!(snip.render(), "fn get()");
+	}
+	#[test]
+	fn coalesced_text() {
+		let mut placeholder = Placeholder::new(vec![Segment::text("hello"), Segment::text("there!")]);
+		placeholder.coalesce_text();
+		println!("{:?}", placeholder);
+		assert_eq!(placeholder.default.len(), 1);
+		assert_eq!(placeholder.default[0].as_text(), Some("hellothere!"));
+		let inner = Rc::new(RefCell::new(Placeholder::new(vec![Segment::text("a"), Segment::text("b")])));
+		let mut snip = SnippetBuilder::new()
+		.text("x")
+		.text("y")
+		.segment(Segment::Interactive(inner.clone()))
+		.text("z")
+		.build();
+		let rendered = snip.render();
+		snip.coalesce_text();
+		assert_eq!(snip.body.len(), 3);
+		assert_eq!(inner.borrow().default.len(), 1);
+		assert_eq!(snip.render(), rendered);
+	}
+	#[test]
+	fn render_into_reused_buffer() {
+		let choice = Rc::new(RefCell::new(Choice::new(vec![vec![Segment::text("a long choice")], vec![Segment::text("short")]])));
+		let snip = SnippetBuilder::new().text("-> ").segment(Segment::Interactive(choice.clone())).build();
+		let mut buf = String::new();
+		snip.render_into(&mut buf);
+		assert_eq!(buf, "-> a long choice");
+		let capacity = buf.capacity();
+		choice.borrow_mut().next();
+		snip.render_into(&mut buf);
+		println!("{}", buf);
+		assert_eq!(buf, "-> short");
+		assert_eq!(buf.capacity(), capacity);
+		assert_eq!(snip.render(), buf);
+	}
+	#[test]
+	fn evaluate_nested_programics() {
+		let variable = Rc::new(RefCell::new(Variable::with_resolver("NAME", |_| String::from("zakhar"))));
+		let missing = Rc::new(RefCell::new(Variable::resolved_by("MISSING", Rc::new(BuiltinResolver::new()))));
+		let inner = Rc::new(RefCell::new(Placeholder::new(vec![Segment::Interactive(variable.clone()), Segment::Interactive(missing)])));
+		let outer: Rc<RefCell<dyn InteractiveSegment>> = Rc::new(RefCell::new(Placeholder::new(vec![
+			Segment::text("hi "),
+			Segment::Interactive(inner)
+		])));
+		assert_eq!(outer.borrow().to_string(), "hi ");
+		let result = outer.borrow_mut().try_evaluate(CodePolicy::Run);
+		println!("{:?}", result);
+		assert_eq!(result, Err(vec![SnippetError::VariableNotFound(String::from("MISSING"))]));
+		assert!(variable.borrow().is_resolved());
+		assert_eq!(outer.borrow().to_string(), "hi zakhar");
+	}
+	#[test]
+	fn variable_names() {
+		let valid = Variable::try_new("TM_FILENAME").unwrap();
+		assert!(valid.is_valid_name());
+		assert_eq!(valid.name_with_braces(), "${TM_FILENAME}");
+		let spaced = Variable::try_new("file name").unwrap_err();
+		println!("{}", spaced);
+		assert_eq!(spaced, SnippetError::InvalidVariableName(String::from("file name")));
+		assert!(Variable::try_new("1ST").is_err());
+		assert!(Variable::try_new("_private2").is_ok());
+		let mut invalid = Variable::with_resolver("file name", |_| String::from("main.rs"));
+		invalid.evaluate();
+		let snip = SnippetBuilder::new()
+		.segment(Segment::from(invalid))
+		.text(" ")
+		.segment(Segment::from(valid))
+		.build();
+		assert_eq!(snip.to_vscode_body(), "main.rs ${TM_FILENAME}");
+	}
+	#[test]
+	fn insert_and_remove_segments() {
+		let mut snip = SnippetBuilder::new().text("fn ").placeholder(1, vec![Segment::text("name")]).text("()").build();
+		let placeholder = Rc::new(RefCell::new(Placeholder::new(vec![Segment::text("args")])));
+		snip.insert_segment(3, Segment::Interactive(placeholder.clone()));
+		snip.push_segment(Segment::from(Code::new("echo")));
+		snip.push_segment(Segment::Interactive(placeholder.clone()));
+		println!("{:?}", snip.tabs);
+		assert_eq!(snip.tabs.len(), 2);
+		assert_eq!(snip.tabs[1].num, 2);
+		assert_eq!(placeholder.borrow().tab_num, Some(2));
+		assert!(std::ptr::addr_eq(Rc::as_ptr(&snip.tabs[1].field), Rc::as_ptr(&placeholder)));
+		assert_eq!(snip.program_filled_text.len(), 1);
+		assert_eq!(snip.render(), "fn name()argsargs");
+		assert!(snip.remove_segment(5).is_some());
+		assert_eq!(snip.tabs.len(), 2);
+		assert!(snip.remove_segment(3).is_some());
+		assert_eq!(snip.tabs.len(), 1);
+		assert!(snip.remove_segment(3).is_some());
+		assert!(snip.program_filled_text.is_empty());
+		assert!(snip.remove_segment(3).is_none());
+	}
+	#[derive(Debug)]
+	struct Counter(usize);
+	impl fmt::Display for Counter {
+		fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			write!(f, "#{}", self.0)
+		}
+	}
+	impl InteractiveSegment for Counter {
+		fn get_type(&self) -> &str {
+			"counter"
+		}
+	}
+	#[test]
+	fn custom_segment_type() {
+		let counter: Rc<RefCell<dyn InteractiveSegment>> = Rc::new(RefCell::new(Counter(1)));
+		assert_eq!(counter.borrow().kind(), SegmentType::Other("counter"));
+		assert!(cast_interactive_segment::<Placeholder>(&*counter.borrow()).is_none());
+		if let Some(custom) = cast_mut_interactive_segment::<Counter>(&mut *counter.borrow_mut()) {
+			custom.0 += 1;
+		}
+		println!("{}", counter.borrow());
+		assert_eq!(counter.borrow().to_string(), "#2");
+		let counter = downcast_rc::<Counter>(counter).unwrap();
+		assert_eq!(counter.borrow().0, 2);
+	}
+	#[test]
+	fn merge_snippets() {
+		let mut header = SnippetBuilder::new().text("// ").placeholder(1, vec![Segment::text("title")]).placeholder(0, vec![]).text("\n").build();
+		let body = SnippetBuilder::new().text("fn ").placeholder(1, vec![Segment::text("name")]).text("() {").placeholder(0, vec![]).text("}").code("true").build();
+		header.merge(body);
+		println!("{}", header.render());
+		let nums: Vec<usize> = header.tabs.iter().map(|tab| tab.num).collect();
+		assert_eq!(nums, [1, 2, 0]);
+		assert_eq!(header.find_tab(2).unwrap().field.borrow().to_string(), "name");
+		assert_eq!(header.find_tab(2).unwrap().field.borrow().tab_num(), Some(2));
+		assert_eq!(header.render(), "// title\nfn name() {}");
+		assert_eq!(header.program_filled_text.len(), 1);
+		assert_eq!(header.caret_offset(), 20);
+	}
+	#[test]
+	fn nesting_depth() {
+		let inner = Placeholder::new(vec![Segment::text("deep")]);
+		let middle = Placeholder::new(vec![Segment::text("b "), Segment::from(inner)]);
+		let outer = Placeholder::new(vec![Segment::text("a "), Segment::from(middle)]);
+		assert_eq!(outer.depth(), 3);
+		let snip = SnippetBuilder::new().segment(Segment::from(outer)).text("!").build();
+		assert_eq!(snip.max_depth(), 3);
+		println!("{}", snip.render_with_limit(2));
+		assert_eq!(snip.render_with_limit(2), "a b …!");
+		assert_eq!(snip.render_with_limit(3), snip.render());
+		assert_eq!(Placeholder::new(vec![]).depth(), 1);
+		assert_eq!(Snippet::new().max_depth(), 0);
+	}
+	#[test]
+	fn trimmed_code_output() {
+		let mut code = Code::new("echo hi");
+		code.try_evaluate().unwrap();
+		assert_eq!(code.output, "hi\n");
+		code.trim_output = TrimMode::TrailingNewline;
+		code.try_evaluate().unwrap();
+		println!("{:?}", code.output);
+		assert_eq!(code.output, "hi");
+		let mut code = Code::new("printf '  hi\\n\\n'");
+		code.trim_output = TrimMode::TrailingNewline;
+		code.try_evaluate().unwrap();
+		assert_eq!(code.output, "  hi\n");
+		code.trim_output = TrimMode::Both;
+		code.try_evaluate().unwrap();
+		assert_eq!(code.output, "hi");
+	}
+	#[test]
+	fn references_by_key() {
+		let code = Rc::new(RefCell::new(Code::new("echo computed")));
+		code.borrow_mut().trim_output = TrimMode::TrailingNewline;
+		let mut snip = SnippetBuilder::new()
+		.reference("author", "zakhar")
+		.segment(Segment::from(ComputedReference::new("output", code.clone())))
+		.build();
+		snip.program_filled_text.push(code);
+		snip.evaluate_all_with(CodePolicy::Run).unwrap();
+		let author = snip.reference("author").unwrap();
+		assert!(Rc::ptr_eq(&author, &snip.references[0]));
+		println!("{:?}", snip.resolve_reference("author"));
+		assert_eq!(snip.resolve_reference("author").as_deref(), Some("zakhar"));
+		assert_eq!(snip.resolve_reference("output").as_deref(), Some("computed"));
+		assert!(snip.reference("output").is_none());
+		assert_eq!(snip.resolve_reference("nothing"), None);
+	}
+	#[test]
+	fn search_choice_branches() {
+		let mut choice = Choice::new(vec![
+			vec![Segment::text("Apple")],
+			vec![Segment::text("banana")],
+			vec![Segment::text("Cherry "), Segment::from(Placeholder::new(vec![Segment::text("pie")]))]
+		]);
+		assert_eq!(choice.find_branch("an"), Some(1));
+		assert_eq!(choice.find_branch("APP"), Some(0));
+		assert_eq!(choice.find_branch_with("APP", MatchMode::Exact), None);
+		assert_eq!(choice.find_branch_with("App", MatchMode::Exact), Some(0));
+		assert!(choice.contains("y p"));
+		assert!(!choice.contains("kiwi"));
+		assert_eq!(choice.select_matching("PIE"), Some(2));
+		println!("{}", choice);
+		assert_eq!(choice.to_string(), "Cherry pie");
+		assert_eq!(choice.select_matching("kiwi"), None);
+		assert_eq!(choice.selected, 2);
+	}
+	#[test]
+	fn pretty_debug_tree() {
+		let snip = SnippetBuilder::new()
+		.text("fn ")
+		.placeholder(1, vec![Segment::text("name"), Segment::from(Placeholder::new(vec![Segment::text("_suffix")]))])
+		.choice(2, vec![vec![Segment::text("()")], vec![Segment::text("(self)")]])
+		.variable("TM_FILENAME")
+		.build();
+		let tree = snip.pretty_debug();
+		println!("{}", tree);
+		assert_eq!(tree, [
+			"text \"fn \"",
+			"placeholder (tab 1)",
+			"  text \"name\"",
+			"  placeholder",
+			"    text \"_suffix\"",
+			"choice selecting 0 of 2 (tab 2)",
+			"  choice 0 (selected)",
+			"    text \"()\"",
+			"  choice 1",
+			"    text \"(self)\"",
+			"variable TM_FILENAME",
+			""
+		].join("\n"));
+	}
+	#[test]
+	fn variable_fallbacks() {
+		let resolver = Rc::new(BuiltinResolver {
+			selected_text: Some(String::new()),
+			current_word: Some(String::from("word")),
+			..BuiltinResolver::new()
+		});
+		let mut variable = Variable::resolved_by("TM_SELECTED_TEXT", resolver.clone());
+		variable.fallbacks = vec![String::from("CLIPBOARD"), String::from("TM_CURRENT_WORD")];
+		variable.try_evaluate().unwrap();
+		println!("{:?}", variable);
+		assert_eq!(variable.value, "word");
+		let mut variable = Variable::resolved_by("TM_SELECTED_TEXT", resolver.clone());
+		variable.fallbacks = vec![String::from("CLIPBOARD")];
+		variable.try_evaluate().unwrap();
+		assert_eq!(variable.value, "");
+		let mut variable = Variable::resolved_by("CLIPBOARD", resolver);
+		variable.fallbacks = vec![String::from("TM_CURRENT_LINE")];
+		assert_eq!(variable.try_evaluate(), Err(SnippetError::VariableNotFound(String::from("CLIPBOARD"))));
+	}
+	#[test]
+	fn map_every_text() {
+		let choice = Rc::new(RefCell::new(Choice::new(vec![vec![Segment::text("one")], vec![Segment::text("two")]])));
+		let mut snip = SnippetBuilder::new()
+		.text("hello ")
+		.placeholder(1, vec![Segment::text("big "), Segment::from(Placeholder::new(vec![Segment::text("world")]))])
+		.segment(Segment::Interactive(choice.clone()))
+		.build();
+		let mut calls = 0;
+		snip.map_text(|text| {
+			calls += 1;
+			text.to_uppercase()
+		});
+		println!("{}", snip.render());
+		assert_eq!(snip.render(), "HELLO BIG WORLDONE");
+		assert_eq!(choice.borrow().option_strings(), ["ONE", "TWO"]);
+		assert_eq!(calls, 5);
+	}
+	#[test]
+	fn indented_render() {
+		let mut snip = SnippetBuilder::new()
+		.text("if ")
+		.placeholder(1, vec![Segment::text("cond")])
+		.text(" {\n")
+		.placeholder(2, vec![Segment::text("first\nsecond")])
+		.text("\n")
+		.code("printf 'a\\nb'")
+		.text("\n}")
+		.build();
+		snip.evaluate_all_with(CodePolicy::Run).unwrap();
+		let indented = snip.render_indented("\t");
+		println!("{}", indented);
+		assert_eq!(indented, "if cond {\n\tfirst\n\tsecond\n\ta\n\tb\n\t}");
+		assert_eq!(SnippetBuilder::new().text("a\n\nb").build().render_indented("  "), "a\n\n  b");
+	}
+	#[test]
+	fn normalized_line_endings() {
+		let mut snip = SnippetBuilder::new()
+		.text("a\r\nb\n")
+		.placeholder(1, vec![Segment::text("c\r\n")])
+		.code("printf 'd\\r\\ne\\n'")
+		.build();
+		snip.evaluate_all_with(CodePolicy::Run).unwrap();
+		snip.normalize_line_endings(LineEnding::Lf);
+		println!("{:?}", snip.render());
+		assert_eq!(snip.render(), "a\nb\nc\nd\ne\n");
+		snip.normalize_line_endings(LineEnding::CrLf);
+		assert_eq!(snip.render(), "a\r\nb\r\nc\r\nd\r\ne\r\n");
+	}
+	#[test]
+	fn guarded_code() {
+		let mut code = Code::new("echo removed && rm -rf nothing");
+		code.output = String::from("stale");
+		code.set_guard(|code| !code.contains("rm -rf"));
+		let err = code.try_evaluate().unwrap_err();
+		println!("{}", err);
+		assert_eq!(err, SnippetError::Blocked(String::from("echo removed && rm -rf nothing")));
+		assert!(code.output.is_empty());
+		assert!(!code.is_evaluated());
+		let mut copy = code.clone();
+		copy.code_to_run = String::from("echo allowed");
+		copy.try_evaluate().unwrap();
+		assert_eq!(copy.output, "allowed\n");
+		println!("{:?}", copy);
+		code.clear_guard();
+		code.code_to_run = String::from("true");
+		assert!(code.try_evaluate().is_ok());
+	}
+	#[test]
+	fn fully_resolved_snippet() {
+		let mut snip = SnippetBuilder::new()
+		.segment(Segment::from(Variable::with_resolver("NAME", |_| String::from("zakhar"))))
+		.code("echo hi")
+		.build();
+		assert!(!snip.is_fully_resolved());
+		snip.evaluate_all_with(CodePolicy::Skip).unwrap();
+		println!("{}", snip.pretty_debug());
+		assert!(!snip.is_fully_resolved());
+		snip.evaluate_all_with(CodePolicy::Run).unwrap();
+		assert!(snip.is_fully_resolved());
+		assert!(Snippet::new().is_fully_resolved());
+	}
+	#[test]
+	fn flat_tokens() {
+		let snip = SnippetBuilder::new()
+		.text("let ")
+		.placeholder(1, vec![Segment::text("x"), Segment::from(Placeholder::new(vec![Segment::text(": i32")]))])
+		.text(" = ")
+		.choice(2, vec![vec![Segment::text("0")], vec![Segment::text("1")]])
+		.variable("TM_SELECTED_TEXT")
+		.build();
+		let tokens = snip.tokens();
+		println!("{:?}", tokens);
+		assert_eq!(tokens, [
+			Token::Text(String::from("let ")),
+			Token::TabStart(1),
+			Token::Text(String::from("x")),
+			Token::Text(String::from(": i32")),
+			Token::TabEnd(1),
+			Token::Text(String::from(" = ")),
+			Token::TabStart(2),
+			Token::ChoiceStart,
+			Token::Text(String::from("0")),
+			Token::ChoiceEnd,
+			Token::TabEnd(2),
+			Token::VariableRef(String::from("TM_SELECTED_TEXT"))
+		]);
+	}
+	#[test]
+	fn placeholder_indexing() {
+		let mut placeholder = Placeholder::new(vec![Segment::text("a"), Segment::from(Placeholder::new(vec![Segment::text("b")]))]);
+		assert_eq!(placeholder.len(), 2);
+		assert!(!placeholder.is_empty());
+		assert_eq!(placeholder[0].to_string(), "a");
+		assert_eq!(placeholder[1].to_string(), "b");
+		placeholder[0] = Segment::text("c");
+		println!("{}", placeholder);
+		assert_eq!(placeholder.to_string(), "cb");
+		placeholder.set_value(vec![]);
+		assert!(placeholder.is_empty());
+	}
+	#[test]
+	fn expand_in_one_call() {
+		struct Names;
+		impl VariableResolver for Names {
+			fn resolve(&self, name: &str) -> Option<String> {
+				match name {
+					"USER" => Some(String::from("zakhar")),
+					"GREETING" => Some(String::from("hello")),
+					_ => None
+				}
+			}
+		}
+		let mut snip = SnippetBuilder::new()
+		.variable("GREETING")
+		.text(" ")
+		.variable("USER")
+		.code("printf '!'")
+		.build();
+		let expanded = snip.expand_with_resolver(&Names, CodePolicy::Run);
+		println!("{:?}", expanded);
+		assert_eq!(expanded, Ok(String::from("hello zakhar!")));
+		let mut missing = SnippetBuilder::new().variable("NOWHERE").build();
+		assert_eq!(missing.expand_with_resolver(&Names, CodePolicy::Run), Err(vec![SnippetError::VariableNotFound(String::from("NOWHERE"))]));
+	}
+	#[test]
+	fn filtered_choice_navigation() {
+		let mut choice = Choice::new(["apple", "banana", "apricot", "cherry", "Avocado"].iter().map(|option| vec![Segment::text(*option)]).collect());
+		assert_eq!(choice.filtered_indices(), [0, 1, 2, 3, 4]);
+		choice.filter = String::from("A");
+		assert_eq!(choice.filtered_indices(), [0, 1, 2, 4]);
+		choice.filter = String::from("ap");
+		assert_eq!(choice.filtered_indices(), [0, 2]);
+		choice.next();
+		assert_eq!(choice.selected_string().as_deref(), Some("apricot"));
+		choice.next();
+		assert_eq!(choice.selected_string().as_deref(), Some("apple"));
+		choice.previous();
+		println!("{:?}", choice.selected_string());
+		assert_eq!(choice.selected_string().as_deref(), Some("apricot"));
+		choice.filter = String::from("zzz");
+		choice.next();
+		assert_eq!(choice.selected, 2);
+	}
+	#[test]
+	fn segment_at_byte_offset() {
+		let snip = SnippetBuilder::new()
+		.text("café ")
+		.placeholder(1, vec![Segment::text("名前")])
+		.text("!")
+		.build();
+		let rendered = snip.render();
+		println!("{} is {} bytes", rendered, rendered.len());
+		assert_eq!(snip.byte_to_segment_index(0), Some(0));
+		assert_eq!(snip.byte_to_segment_index(5), Some(0));
+		assert_eq!(snip.byte_to_segment_index(6), Some(1));
+		assert_eq!(snip.byte_to_segment_index(11), Some(1));
+		assert_eq!(snip.byte_to_segment_index(12), Some(2));
+		assert_eq!(snip.byte_to_segment_index(13), None);
+	}
+	#[test]
+	fn env_prefix() {
+		std::env::set_var("SNIPPET_GREET", "hello");
+		let mut var = Variable::new("GREET");
+		var.env_prefix = Some(String::from("SNIPPET_"));
+		var.try_evaluate().unwrap();
+		println!("{:?}", var);
+		assert_eq!(var.value, "hello");
+		assert_eq!(var.indentifier(), "GREET");
+		var.env_prefix = Some(String::from("SNIPPET_SEGMENT_TYPES_UNSET_"));
+		assert_eq!(var.try_evaluate(), Err(SnippetError::VariableNotFound(String::from("GREET"))));
+	}
+	#[test]
+	fn replace_shared_variable_value() {
+		let shared = Rc::new(RefCell::new(Variable::with_resolver("TM_SELECTED_TEXT", |_| String::from("old"))));
+		let mut snip = SnippetBuilder::new()
+		.segment(Segment::Interactive(shared.clone()))
+		.text(" | ")
+		.placeholder(1, vec![Segment::Interactive(shared.clone())])
+		.text(" | ")
+		.variable("TM_SELECTED_TEXT")
+		.text(" | ")
+		.variable("SNIPPET_SEGMENT_TYPES_UNSET_VARIABLE")
+		.build();
+		snip.replace_variable_value("TM_SELECTED_TEXT", String::from("new"));
+		println!("{}", snip.render());
+		assert_eq!(snip.render(), "new | new | new | ");
+		assert!(shared.borrow().is_resolved());
+		assert_eq!(snip.evaluate_all(), Err(vec![SnippetError::VariableNotFound(String::from("SNIPPET_SEGMENT_TYPES_UNSET_VARIABLE"))]));
+		assert_eq!(shared.borrow().value, "new");
+	}
+	#[test]
+	fn pluggable_executor() {
+		struct Fixed;
+		impl CodeExecutor for Fixed {
+			fn run(&self, code: &str, env: &[(String, String)]) -> Result<CodeResult, SnippetError> {
+				Ok(CodeResult {
+					exit_code: if code == "fail" { 3 } else { 0 },
+					stdout: format!("ran {} with {} variables\n", code, env.len()),
+					stderr: String::new()
+				})
+			}
+		}
+		let mut code = Code::new("anything");
+		code.env.push((String::from("TM_SELECTED_TEXT"), String::from("x")));
+		code.trim_output = TrimMode::TrailingNewline;
+		code.set_executor(Fixed);
+		code.try_evaluate().unwrap();
+		println!("{:?}", code);
+		assert_eq!(code.output, "ran anything with 1 variables");
+		assert!(code.is_evaluated());
+		let mut lines = vec![];
+		code.evaluate_streaming(|line| lines.push(String::from(line))).unwrap();
+		assert_eq!(lines, ["ran anything with 1 variables"]);
+		code.code_to_run = String::from("fail");
+		assert!(matches!(code.try_evaluate(), Err(SnippetError::ScriptFailed { code: Some(3), .. })));
+		code.clear_executor();
+		code.code_to_run = String::from("printf native");
+		code.try_evaluate().unwrap();
+		assert_eq!(code.output, "native");
+	}
+	#[test]
+	fn segments_refer_same() {
+		let reference = Rc::new(RefCell::new(Reference::Text(String::from("key"), String::from("value"))));
+		let shared = Segment::Reference(reference.clone());
+		let mirror = Segment::Reference(reference);
+		let distinct = Segment::Reference(Rc::new(RefCell::new(Reference::Text(String::from("key"), String::from("value")))));
+		assert!(*distinct.as_reference().unwrap().borrow() == shared);
+		assert!(!distinct.refers_same(&shared));
+		assert!(mirror.refers_same(&shared));
+		let field = Rc::new(RefCell::new(Placeholder::new(vec![Segment::text("x")])));
+		let tab = Segment::Interactive(field.clone());
+		let tab_mirror = Segment::Interactive(field);
+		let copy = tab.deep_clone();
+		println!("{} {} {}", tab, tab_mirror, copy);
+		assert!(tab.refers_same(&tab_mirror));
+		assert!(!tab.refers_same(&copy));
+		assert!(!Segment::text("x").refers_same(&Segment::text("x")));
+	}
+	#[test]
+	fn emptiness_of_segment_within_itself() {
+		let field = Rc::new(RefCell::new(Placeholder::new(vec![])));
+		field.borrow_mut().default.push(Segment::Interactive(field.clone()));
+		let segment = Segment::Interactive(field.clone());
+		println!("{}", segment);
+		assert!(!segment.is_empty());
+		assert_eq!(segment.rendered_len(), segment.to_string().chars().count());
+		let mut snip = Snippet {
+			body: vec![segment],
+			tabs: vec![Tab { num: 1, field: field.clone() }],
+			program_filled_text: vec![],
+			references: vec![]
+		};
+		snip.trim_empty_tabs_deep();
+		assert_eq!(snip.tabs.len(), 1);
+		field.borrow_mut().default.clear();
+		snip.trim_empty_tabs_deep();
+		assert!(snip.tabs.is_empty());
+	}
+	#[test]
+	fn export_segment_within_itself() {
+		let field = Rc::new(RefCell::new(Placeholder::new(vec![Segment::text("a")])));
+		field.borrow_mut().default.push(Segment::Interactive(field.clone()));
+		let choice = Rc::new(RefCell::new(Choice::new(vec![vec![Segment::text("b")]])));
+		choice.borrow_mut().choices[0].push(Segment::Interactive(choice.clone()));
+		let snip = Snippet {
+			body: vec![Segment::Interactive(field.clone()), Segment::Interactive(choice.clone())],
+			tabs: vec![Tab { num: 1, field }],
+			program_filled_text: vec![],
+			references: vec![]
+		};
+		let body = snip.to_vscode_body();
+		println!("{}", body);
+		assert_eq!(body, "${1:a…}b…");
+		assert_eq!(snip.to_lsp_snippet(), body);
+		assert_eq!(snip.to_textmate_body(), body);
+	}
+	#[test]
+	fn compare_segments_within_themselves() {
+		let nested_in_itself = |text: &str| {
+			let field = Rc::new(RefCell::new(Placeholder::new(vec![Segment::text(text)])));
+			field.borrow_mut().default.push(Segment::Interactive(field.clone()));
+			field
+		};
+		let first = nested_in_itself("a");
+		let second = nested_in_itself("a");
+		println!("{}", first.borrow());
+		assert!(*first.borrow() == *second.borrow());
+		assert!(*first.borrow() != *nested_in_itself("b").borrow());
+		let unrolled = Rc::new(RefCell::new(Placeholder::new(vec![Segment::text("a"), Segment::Interactive(first.clone())])));
+		assert!(*unrolled.borrow() == *second.borrow());
+	}
+	#[test]
+	fn hash_segments_within_themselves() {
+		use std::collections::hash_map::DefaultHasher;
+		let hash = |placeholder: &Placeholder| {
+			let mut hasher = DefaultHasher::new();
+			placeholder.hash(&mut hasher);
+			hasher.finish()
+		};
+		let nested_in_itself = |text: &str| {
+			let field = Rc::new(RefCell::new(Placeholder::new(vec![Segment::text(text)])));
+			field.borrow_mut().default.push(Segment::Interactive(field.clone()));
+			field
+		};
+		let first = nested_in_itself("a");
+		let second = nested_in_itself("a");
+		println!("{}", hash(&first.borrow()));
+		assert_eq!(hash(&first.borrow()), hash(&second.borrow()));
+		assert_ne!(hash(&first.borrow()), hash(&nested_in_itself("b").borrow()));
 	}
 }
@@ -0,0 +1,1013 @@
+//! Serde support, enabled by the `serde` feature.
+//! Segments are tagged by `type`, which is `text`, `reference` or the `get_type` of an interactive segment.
+//! Only the interactive segments of this library can be serialized.
+//! Within a snippet a segment found again is serialized as `shared` or `shared_reference`, see [`SerializableSnippet`].
+use crate::*;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use serde::de::Error as _;
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum TaggedRef<'a> {
+	Text {
+		text: &'a str
+	},
+	Reference {
+		key: &'a str,
+		value: &'a str
+	},
+	Placeholder(&'a Placeholder),
+	Choice(&'a Choice),
+	Variable(&'a Variable),
+	Code(&'a Code),
+	Shared {
+		node: usize
+	},
+	#[serde(rename = "shared_reference")]
+	SharedReference {
+		reference: usize
+	}
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Tagged {
+	Text {
+		text: String
+	},
+	Reference {
+		key: String,
+		value: String
+	},
+	Placeholder(Placeholder),
+	Choice(Choice),
+	Variable(Variable),
+	Code(Code),
+	Shared {
+		node: usize
+	},
+	#[serde(rename = "shared_reference")]
+	SharedReference {
+		reference: usize
+	}
+}
+impl Tagged {
+	/// Segment deserialized, failing if it is shared outside of a snippet.
+	fn into_segment<E: serde::de::Error>(self) -> Result<Segment, E> {
+		Ok(match self {
+			Tagged::Text { text } => Segment::Text(text),
+			Tagged::Reference { key, value } => Segment::Reference(Rc::new(RefCell::new(Reference::Text(key, value)))),
+			Tagged::Placeholder(placeholder) => Segment::Interactive(Rc::new(RefCell::new(placeholder))),
+			Tagged::Choice(choice) => Segment::Interactive(Rc::new(RefCell::new(choice))),
+			Tagged::Variable(variable) => Segment::Interactive(Rc::new(RefCell::new(variable))),
+			Tagged::Code(code) => Segment::Interactive(Rc::new(RefCell::new(code))),
+			Tagged::Shared { .. } | Tagged::SharedReference { .. } if !DESERIALIZING_SNIPPET.with(Cell::get) => {
+				return Err(E::custom("shared segments can only be deserialized within a snippet"))
+			},
+			Tagged::Shared { node } => Segment::Interactive(Rc::new(RefCell::new(Shared::Node(node)))),
+			Tagged::SharedReference { reference } => Segment::Interactive(Rc::new(RefCell::new(Shared::Reference(reference))))
+		})
+	}
+}
+fn into_segments<E: serde::de::Error>(tagged: Vec<Tagged>) -> Result<Vec<Segment>, E> {
+	tagged.into_iter().map(Tagged::into_segment).collect()
+}
+
+thread_local! {
+	/// Interactive segments and references the snippet being serialized on this thread has serialized so far, if a snippet is being serialized.
+	static SERIALIZED: RefCell<Option<(Vec<*const ()>, Vec<*const ()>)>> = const { RefCell::new(None) };
+	/// Interactive segments in the midst of being serialized on this thread outside of a snippet, to tell when one is nested within itself.
+	static SERIALIZING: RefCell<Vec<*const ()>> = const { RefCell::new(vec![]) };
+	/// Whether a snippet is being deserialized on this thread, the only place shared segments can be deserialized.
+	static DESERIALIZING_SNIPPET: Cell<bool> = const { Cell::new(false) };
+}
+/// Tracks what a snippet serializes until dropped, see [`SERIALIZED`].
+struct SerializingSnippet(Option<(Vec<*const ()>, Vec<*const ()>)>);
+impl SerializingSnippet {
+	fn start() -> SerializingSnippet {
+		SerializingSnippet(SERIALIZED.with(|serialized| serialized.replace(Some((vec![], vec![])))))
+	}
+}
+impl Drop for SerializingSnippet {
+	fn drop(&mut self) {
+		let previous = self.0.take();
+		SERIALIZED.with(|serialized| *serialized.borrow_mut() = previous);
+	}
+}
+/// Allows shared segments to be deserialized until dropped, see [`DESERIALIZING_SNIPPET`].
+struct DeserializingSnippet(bool);
+impl DeserializingSnippet {
+	fn start() -> DeserializingSnippet {
+		DeserializingSnippet(DESERIALIZING_SNIPPET.with(|deserializing| deserializing.replace(true)))
+	}
+}
+impl Drop for DeserializingSnippet {
+	fn drop(&mut self) {
+		DESERIALIZING_SNIPPET.with(|deserializing| deserializing.set(self.0));
+	}
+}
+/// Index of `pointer` among those serialized before, which it is added to if it is not one of them.
+fn serialized_before(serialized: &mut Vec<*const ()>, pointer: *const ()) -> Option<usize> {
+	let index = serialized.iter().position(|other| *other == pointer);
+	if index.is_none() {
+		serialized.push(pointer);
+	}
+	index
+}
+
+/// Stands in for a shared segment until [`DeserializedSnippet`] puts the segment serialized before in its place.
+#[derive(Debug, Clone, Copy)]
+enum Shared {
+	Node(usize),
+	Reference(usize)
+}
+impl fmt::Display for Shared {
+	fn fmt(&self, _: &mut fmt::Formatter) -> fmt::Result {
+		Ok(())
+	}
+}
+impl InteractiveSegment for Shared {
+	fn get_type(&self) -> &str {
+		"shared"
+	}
+}
+
+fn serialize_interactive<S: Serializer>(interactive: &dyn InteractiveSegment, serializer: S) -> Result<S::Ok, S::Error> {
+	if let Some(placeholder) = cast_interactive_segment::<Placeholder>(interactive) {
+		TaggedRef::Placeholder(placeholder).serialize(serializer)
+	} else if let Some(choice) = cast_interactive_segment::<Choice>(interactive) {
+		TaggedRef::Choice(choice).serialize(serializer)
+	} else if let Some(variable) = cast_interactive_segment::<Variable>(interactive) {
+		TaggedRef::Variable(variable).serialize(serializer)
+	} else if let Some(code) = cast_interactive_segment::<Code>(interactive) {
+		TaggedRef::Code(code).serialize(serializer)
+	} else {
+		Err(S::Error::custom(format!("can not serialize {} segments", interactive.get_type())))
+	}
+}
+
+/// Serializes the interactive segment at `pointer`, as shared if the snippet being serialized has serialized it before.
+/// Outside of a snippet fails on a segment nested within itself rather than recursing forever.
+fn serialize_node<S: Serializer>(pointer: *const (), interactive: &dyn InteractiveSegment, serializer: S) -> Result<S::Ok, S::Error> {
+	let shared = SERIALIZED.with(|serialized| serialized.borrow_mut().as_mut().map(|(interactives, _)| serialized_before(interactives, pointer)));
+	match shared {
+		Some(Some(node)) => TaggedRef::Shared { node }.serialize(serializer),
+		Some(None) => serialize_interactive(interactive, serializer),
+		None => {
+			if SERIALIZING.with(|serializing| serializing.borrow().contains(&pointer)) {
+				return Err(S::Error::custom(format!("can not serialize a {} segment nested within itself outside of a snippet", interactive.get_type())))
+			}
+			SERIALIZING.with(|serializing| serializing.borrow_mut().push(pointer));
+			let result = serialize_interactive(interactive, serializer);
+			SERIALIZING.with(|serializing| serializing.borrow_mut().pop());
+			result
+		}
+	}
+}
+
+struct SegmentRef<'a>(&'a Segment);
+impl Serialize for SegmentRef<'_> {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		match self.0 {
+			Segment::Text(text) => TaggedRef::Text { text }.serialize(serializer),
+			Segment::Interactive(interactive) => serialize_node(Rc::as_ptr(interactive) as *const (), &*interactive.borrow(), serializer),
+			Segment::Reference(reference) => {
+				let pointer = Rc::as_ptr(reference) as *const ();
+				let shared = SERIALIZED.with(|serialized| serialized.borrow_mut().as_mut().and_then(|(_, references)| serialized_before(references, pointer)));
+				if let Some(reference) = shared {
+					return TaggedRef::SharedReference { reference }.serialize(serializer)
+				}
+				let Reference::Text(key, value) = &*reference.borrow();
+				TaggedRef::Reference { key, value }.serialize(serializer)
+			}
+		}
+	}
+}
+
+struct SegmentsRef<'a>(&'a [Segment]);
+impl Serialize for SegmentsRef<'_> {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_seq(self.0.iter().map(SegmentRef))
+	}
+}
+
+/// For `#[serde(with)]` on a `Vec<Segment>`.
+pub(crate) mod segments {
+	use super::*;
+	pub fn serialize<S: Serializer>(segments: &[Segment], serializer: S) -> Result<S::Ok, S::Error> {
+		SegmentsRef(segments).serialize(serializer)
+	}
+	pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Segment>, D::Error> {
+		into_segments(Vec::<Tagged>::deserialize(deserializer)?)
+	}
+}
+
+/// For `#[serde(with)]` on an `Option<Vec<Segment>>`.
+pub(crate) mod optional_segments {
+	use super::*;
+	pub fn serialize<S: Serializer>(segments: &Option<Vec<Segment>>, serializer: S) -> Result<S::Ok, S::Error> {
+		segments.as_deref().map(SegmentsRef).serialize(serializer)
+	}
+	pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<Segment>>, D::Error> {
+		Option::<Vec<Tagged>>::deserialize(deserializer)?.map(into_segments).transpose()
+	}
+}
+
+/// For `#[serde(with)]` on a `regex::Regex`, which is stored as its pattern.
+#[cfg(feature = "regex")]
+pub(crate) mod regex_pattern {
+	use super::*;
+	pub fn serialize<S: Serializer>(pattern: &regex::Regex, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_str(pattern.as_str())
+	}
+	pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<regex::Regex, D::Error> {
+		let pattern = String::deserialize(deserializer)?;
+		regex::Regex::new(&pattern).map_err(D::Error::custom)
+	}
+}
+
+impl Serialize for Choice {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		#[derive(Serialize)]
+		struct ChoiceRef<'a> {
+			selected: usize,
+			choices: Vec<SegmentsRef<'a>>,
+			tab_num: Option<usize>
+		}
+		ChoiceRef {
+			selected: self.selected,
+			choices: self.choices.iter().map(|choice| SegmentsRef(choice)).collect(),
+			tab_num: self.tab_num
+		}.serialize(serializer)
+	}
+}
+impl<'de> Deserialize<'de> for Choice {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Choice, D::Error> {
+		#[derive(Deserialize)]
+		struct ChoiceDef {
+			selected: usize,
+			choices: Vec<Vec<Tagged>>,
+			#[serde(default)]
+			tab_num: Option<usize>
+		}
+		let ChoiceDef { selected, choices, tab_num } = ChoiceDef::deserialize(deserializer)?;
+		Ok(Choice {
+			selected,
+			choices: choices.into_iter().map(into_segments).collect::<Result<_, _>>()?,
+			tab_num,
+			filter: String::new()
+		})
+	}
+}
+
+/// Interactive segments and references of a body in a fixed order so tabs and the like can point at them by index.
+/// Placeholders are walked into by default then value and choices by every choice, which is also the order they are serialized in.
+/// Each is only found once, those found again are serialized as shared.
+#[derive(Default)]
+struct Nodes {
+	interactives: Vec<Rc<RefCell<dyn InteractiveSegment>>>,
+	references: Vec<Rc<RefCell<Reference>>>
+}
+impl Nodes {
+	fn of(body: &[Segment]) -> Nodes {
+		let mut nodes = Nodes::default();
+		nodes.collect(body);
+		nodes
+	}
+	fn collect(&mut self, segments: &[Segment]) {
+		for segment in segments {
+			match segment {
+				Segment::Text(_) => {},
+				Segment::Reference(reference) => if self.reference_index(reference).is_none() {
+					self.references.push(Rc::clone(reference))
+				},
+				Segment::Interactive(interactive) => {
+					let pointer = Rc::as_ptr(interactive) as *const ();
+					if self.interactive_index(pointer).is_some() {
+						continue
+					}
+					self.interactives.push(Rc::clone(interactive));
+					let interactive = interactive.borrow();
+					if let Some(placeholder) = cast_interactive_segment::<Placeholder>(&*interactive) {
+						self.collect(&placeholder.default);
+						if let Some(value) = &placeholder.value {
+							self.collect(value);
+						}
+					} else if let Some(Choice { choices, .. }) = cast_interactive_segment::<Choice>(&*interactive) {
+						for choice in choices {
+							self.collect(choice);
+						}
+					} else if let Some(variable) = cast_interactive_segment::<Variable>(&*interactive) {
+						self.collect(&variable.default);
+					}
+				}
+			}
+		}
+	}
+	fn interactive_index(&self, pointer: *const ()) -> Option<usize> {
+		self.interactives.iter().position(|interactive| Rc::as_ptr(interactive) as *const () == pointer)
+	}
+	fn reference_index(&self, reference: &Rc<RefCell<Reference>>) -> Option<usize> {
+		self.references.iter().position(|other| Rc::ptr_eq(other, reference))
+	}
+	/// Puts the segments serialized before back in place of the shared segments they were deserialized as,
+	/// collecting the rest in the order they were serialized in.
+	fn link<E: serde::de::Error>(&mut self, segments: &mut [Segment]) -> Result<(), E> {
+		for segment in segments {
+			self.link_segment(segment)?;
+		}
+		Ok(())
+	}
+	fn link_segment<E: serde::de::Error>(&mut self, segment: &mut Segment) -> Result<(), E> {
+		let interactive = match segment {
+			Segment::Text(_) => return Ok(()),
+			Segment::Reference(reference) => {
+				self.references.push(Rc::clone(reference));
+				return Ok(())
+			},
+			Segment::Interactive(interactive) => interactive
+		};
+		let shared = cast_interactive_segment::<Shared>(&*interactive.borrow()).copied();
+		match shared {
+			Some(Shared::Node(node)) => {
+				*segment = Segment::Interactive(self.interactives.get(node).cloned().ok_or_else(|| E::custom(format!("no segment {} serialized before", node)))?);
+			},
+			Some(Shared::Reference(index)) => {
+				*segment = Segment::Reference(self.references.get(index).cloned().ok_or_else(|| E::custom(format!("no reference {} serialized before", index)))?);
+			},
+			None => {
+				self.interactives.push(Rc::clone(interactive));
+				for nested in every_nested_mut(&mut *interactive.borrow_mut()) {
+					self.link(nested)?;
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Where a tab field, programic segment or reference of a snippet is found.
+/// Either the index of a segment within its body or, if it is not within the body, the segment itself.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum NodeRef<T> {
+	Body(usize),
+	Inline(T)
+}
+
+struct FieldRef<'a>(&'a RefCell<dyn Field>);
+impl Serialize for FieldRef<'_> {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serialize_node(self.0 as *const RefCell<dyn Field> as *const (), &*self.0.borrow(), serializer)
+	}
+}
+struct ProgramicRef<'a>(&'a RefCell<dyn Programic>);
+impl Serialize for ProgramicRef<'_> {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serialize_node(self.0 as *const RefCell<dyn Programic> as *const (), &*self.0.borrow(), serializer)
+	}
+}
+#[derive(Serialize, Deserialize)]
+struct ReferenceDef {
+	key: String,
+	value: String
+}
+
+#[derive(Serialize)]
+struct TabRef<'a> {
+	num: usize,
+	field: NodeRef<FieldRef<'a>>
+}
+#[derive(Serialize)]
+struct SnippetRef<'a> {
+	body: SegmentsRef<'a>,
+	tabs: Vec<TabRef<'a>>,
+	program_filled_text: Vec<NodeRef<ProgramicRef<'a>>>,
+	references: Vec<NodeRef<ReferenceDef>>
+}
+
+#[derive(Deserialize)]
+struct TabDef {
+	num: usize,
+	field: NodeRef<Tagged>
+}
+#[derive(Deserialize)]
+struct SnippetDef {
+	#[serde(with = "segments")]
+	body: Vec<Segment>,
+	tabs: Vec<TabDef>,
+	program_filled_text: Vec<NodeRef<Tagged>>,
+	references: Vec<NodeRef<ReferenceDef>>
+}
+
+/// JSON Schema of what [`SerializableSnippet`] produces as JSON, see [`SerializableSnippet::json_schema`].
+/// The transforms of enabled features are put in place of `/*transforms*/`.
+const JSON_SCHEMA: &str = r##"{
+	"$schema": "https://json-schema.org/draft/2020-12/schema",
+	"title": "Snippet",
+	"type": "object",
+	"properties": {
+		"body": { "$ref": "#/$defs/segments" },
+		"tabs": {
+			"type": "array",
+			"items": {
+				"type": "object",
+				"properties": {
+					"num": { "type": "integer", "minimum": 0 },
+					"field": { "$ref": "#/$defs/node" }
+				},
+				"required": ["num", "field"]
+			}
+		},
+		"program_filled_text": {
+			"type": "array",
+			"items": { "$ref": "#/$defs/node" }
+		},
+		"references": {
+			"type": "array",
+			"items": {
+				"oneOf": [
+					{ "$ref": "#/$defs/body_index" },
+					{
+						"type": "object",
+						"properties": {
+							"inline": {
+								"type": "object",
+								"properties": {
+									"key": { "type": "string" },
+									"value": { "type": "string" }
+								},
+								"required": ["key", "value"]
+							}
+						},
+						"required": ["inline"]
+					}
+				]
+			}
+		}
+	},
+	"required": ["body", "tabs", "program_filled_text", "references"],
+	"$defs": {
+		"segments": {
+			"type": "array",
+			"items": { "$ref": "#/$defs/segment" }
+		},
+		"segment": {
+			"oneOf": [
+				{
+					"type": "object",
+					"properties": {
+						"type": { "const": "text" },
+						"text": { "type": "string" }
+					},
+					"required": ["type", "text"]
+				},
+				{
+					"type": "object",
+					"properties": {
+						"type": { "const": "reference" },
+						"key": { "type": "string" },
+						"value": { "type": "string" }
+					},
+					"required": ["type", "key", "value"]
+				},
+				{ "$ref": "#/$defs/placeholder" },
+				{ "$ref": "#/$defs/choice" },
+				{ "$ref": "#/$defs/variable" },
+				{ "$ref": "#/$defs/code" },
+				{
+					"type": "object",
+					"properties": {
+						"type": { "const": "shared" },
+						"node": { "type": "integer", "minimum": 0 }
+					},
+					"required": ["type", "node"]
+				},
+				{
+					"type": "object",
+					"properties": {
+						"type": { "const": "shared_reference" },
+						"reference": { "type": "integer", "minimum": 0 }
+					},
+					"required": ["type", "reference"]
+				}
+			]
+		},
+		"placeholder": {
+			"type": "object",
+			"properties": {
+				"type": { "const": "placeholder" },
+				"default": { "$ref": "#/$defs/segments" },
+				"value": {
+					"oneOf": [
+						{ "$ref": "#/$defs/segments" },
+						{ "type": "null" }
+					]
+				},
+				"tab_num": { "type": ["integer", "null"], "minimum": 0 }
+			},
+			"required": ["type", "default"]
+		},
+		"choice": {
+			"type": "object",
+			"properties": {
+				"type": { "const": "choice" },
+				"selected": { "type": "integer", "minimum": 0 },
+				"choices": {
+					"type": "array",
+					"items": { "$ref": "#/$defs/segments" }
+				},
+				"tab_num": { "type": ["integer", "null"], "minimum": 0 }
+			},
+			"required": ["type", "selected", "choices"]
+		},
+		"variable": {
+			"type": "object",
+			"properties": {
+				"type": { "const": "variable" },
+				"name": { "type": "string" },
+				"value": { "type": "string" },
+				"default": { "$ref": "#/$defs/segments" },
+				"env_prefix": { "type": ["string", "null"] },
+				"interpolate": { "type": "boolean" },
+				"fallbacks": {
+					"type": "array",
+					"items": { "type": "string" }
+				},
+				"transform": {
+					"oneOf": [
+						{ "enum": ["Upcase", "Downcase", "Capitalize", "PascalCase", "CamelCase"] },/*transforms*/
+						{ "type": "null" }
+					]
+				},
+				"resolved": { "type": "boolean" },
+				"overridden": { "type": "boolean" }
+			},
+			"required": ["type", "name", "value"]
+		},
+		"code": {
+			"type": "object",
+			"properties": {
+				"type": { "const": "code" },
+				"code_to_run": { "type": "string" },
+				"output": { "type": "string" },
+				"stderr": { "type": "string" },
+				"last_exit_code": { "type": ["integer", "null"] },
+				"runner": {
+					"oneOf": [
+						{ "enum": ["System", "Sh", "Bash", "Python", "Node"] },
+						{
+							"type": "object",
+							"properties": {
+								"Custom": { "type": "string" }
+							},
+							"required": ["Custom"]
+						}
+					]
+				},
+				"timeout": {
+					"oneOf": [
+						{
+							"type": "object",
+							"properties": {
+								"secs": { "type": "integer", "minimum": 0 },
+								"nanos": { "type": "integer", "minimum": 0 }
+							},
+							"required": ["secs", "nanos"]
+						},
+						{ "type": "null" }
+					]
+				},
+				"env": {
+					"type": "array",
+					"items": {
+						"type": "array",
+						"prefixItems": [{ "type": "string" }, { "type": "string" }],
+						"items": false
+					}
+				},
+				"args": {
+					"type": "array",
+					"items": { "type": "string" }
+				},
+				"working_dir": { "type": ["string", "null"] },
+				"trim_output": { "enum": ["None", "TrailingNewline", "Both"] },
+				"cache": { "type": "boolean" },
+				"evaluated": { "type": "boolean" }
+			},
+			"required": ["type", "code_to_run", "output", "stderr", "runner", "env", "args", "cache"]
+		},
+		"body_index": {
+			"type": "object",
+			"properties": {
+				"body": { "type": "integer", "minimum": 0 }
+			},
+			"required": ["body"]
+		},
+		"node": {
+			"oneOf": [
+				{ "$ref": "#/$defs/body_index" },
+				{
+					"type": "object",
+					"properties": {
+						"inline": { "$ref": "#/$defs/segment" }
+					},
+					"required": ["inline"]
+				}
+			]
+		}
+	}
+}
+"##;
+#[cfg(feature = "regex")]
+const REGEX_TRANSFORM_SCHEMA: &str = r##"
+						{
+							"type": "object",
+							"properties": {
+								"Regex": {
+									"type": "object",
+									"properties": {
+										"pattern": { "type": "string" },
+										"replacement": { "type": "string" },
+										"global": { "type": "boolean" }
+									},
+									"required": ["pattern", "replacement", "global"]
+								}
+							},
+							"required": ["Regex"]
+						},"##;
+#[cfg(feature = "chrono")]
+const DATE_TIME_TRANSFORM_SCHEMA: &str = r##"
+						{
+							"type": "object",
+							"properties": {
+								"DateTime": { "type": "string" }
+							},
+							"required": ["DateTime"]
+						},"##;
+
+/// Serializes a snippet, which can not implement Serialize itself as it lives in snippet_body.
+/// Tabs, programic segments and references that are also within the body are stored as indexes into it
+/// so that they are still shared once deserialized with [`DeserializedSnippet`].
+/// Likewise an interactive segment or reference found again, such as a mirror or a placeholder nested within itself,
+/// is serialized as `shared` with the index of where it was first serialized.
+pub struct SerializableSnippet<'a>(pub &'a Snippet);
+impl Serialize for SerializableSnippet<'_> {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		let snippet = self.0;
+		let nodes = Nodes::of(&snippet.body);
+		let _serializing = SerializingSnippet::start();
+		SnippetRef {
+			body: SegmentsRef(&snippet.body),
+			tabs: snippet.tabs.iter().map(|tab| TabRef {
+				num: tab.num,
+				field: match nodes.interactive_index(Rc::as_ptr(&tab.field) as *const ()) {
+					Some(index) => NodeRef::Body(index),
+					None => NodeRef::Inline(FieldRef(&tab.field))
+				}
+			}).collect(),
+			program_filled_text: snippet.program_filled_text.iter().map(|programic| {
+				match nodes.interactive_index(Rc::as_ptr(programic) as *const ()) {
+					Some(index) => NodeRef::Body(index),
+					None => NodeRef::Inline(ProgramicRef(programic))
+				}
+			}).collect(),
+			references: snippet.references.iter().map(|reference| {
+				match nodes.reference_index(reference) {
+					Some(index) => NodeRef::Body(index),
+					None => {
+						let Reference::Text(key, value) = &*reference.borrow();
+						NodeRef::Inline(ReferenceDef {
+							key: key.clone(),
+							value: value.clone()
+						})
+					}
+				}
+			}).collect()
+		}.serialize(serializer)
+	}
+}
+impl SerializableSnippet<'_> {
+	/// JSON Schema (draft 2020-12) of the JSON a snippet is serialized as, for tooling such as form generators and validators.
+	/// Body indexes count the interactive segments or references of the body in the order they are serialized,
+	/// shared ones count those of the body then of inline tabs and programic segments.
+	/// Transforms are only in the schema if the feature they need is enabled.
+	pub fn json_schema() -> String {
+		#[allow(unused_mut)]
+		let mut transforms = String::new();
+		#[cfg(feature = "regex")]
+		transforms.push_str(REGEX_TRANSFORM_SCHEMA);
+		#[cfg(feature = "chrono")]
+		transforms.push_str(DATE_TIME_TRANSFORM_SCHEMA);
+		JSON_SCHEMA.replace("/*transforms*/", &transforms)
+	}
+}
+
+/// Snippet deserialized from what [`SerializableSnippet`] produces.
+pub struct DeserializedSnippet(pub Snippet);
+impl DeserializedSnippet {
+	pub fn into_inner(self) -> Snippet {
+		self.0
+	}
+}
+impl<'de> Deserialize<'de> for DeserializedSnippet {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<DeserializedSnippet, D::Error> {
+		let _deserializing = DeserializingSnippet::start();
+		let SnippetDef { mut body, tabs, program_filled_text, references } = SnippetDef::deserialize(deserializer)?;
+		let mut nodes = Nodes::default();
+		nodes.link(&mut body)?;
+		let body_interactives = nodes.interactives.len();
+		let body_references = nodes.references.len();
+		let mut interactive = |node: NodeRef<Tagged>| match node {
+			NodeRef::Body(index) if index < body_interactives => Ok(nodes.interactives[index].clone()),
+			NodeRef::Body(index) => Err(D::Error::custom(format!("no segment {} within the body", index))),
+			NodeRef::Inline(tagged) => {
+				let mut segment = tagged.into_segment()?;
+				nodes.link_segment(&mut segment)?;
+				match segment {
+					Segment::Interactive(interactive) => Ok(interactive),
+					_ => Err(D::Error::custom("expected an interactive segment"))
+				}
+			}
+		};
+		let mut snippet_tabs = vec![];
+		for tab in tabs {
+			let field: Rc<RefCell<dyn Field>> = match downcast_rc::<Placeholder>(interactive(tab.field)?) {
+				Ok(placeholder) => placeholder,
+				Err(interactive) => match downcast_rc::<Choice>(interactive) {
+					Ok(choice) => choice,
+					Err(_) => return Err(D::Error::custom(format!("field of tab {} is neither a placeholder nor a choice", tab.num)))
+				}
+			};
+			snippet_tabs.push(Tab {
+				num: tab.num,
+				field
+			});
+		}
+		let mut snippet_program_filled_text = vec![];
+		for programic in program_filled_text {
+			let programic: Rc<RefCell<dyn Programic>> = match downcast_rc::<Variable>(interactive(programic)?) {
+				Ok(variable) => variable,
+				Err(interactive) => match downcast_rc::<Code>(interactive) {
+					Ok(code) => code,
+					Err(_) => return Err(D::Error::custom("programic segment is neither a variable nor code"))
+				}
+			};
+			snippet_program_filled_text.push(programic);
+		}
+		let mut snippet_references = vec![];
+		for reference in references {
+			snippet_references.push(match reference {
+				NodeRef::Body(index) if index < body_references => nodes.references[index].clone(),
+				NodeRef::Body(index) => return Err(D::Error::custom(format!("no reference {} within the body", index))),
+				NodeRef::Inline(ReferenceDef { key, value }) => Rc::new(RefCell::new(Reference::Text(key, value)))
+			});
+		}
+		Ok(DeserializedSnippet(Snippet {
+			body,
+			tabs: snippet_tabs,
+			program_filled_text: snippet_program_filled_text,
+			references: snippet_references
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn json_round_trip() {
+		let placeholder = Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from("name"))])));
+		let choice = Rc::new(RefCell::new(Choice::try_new(1, vec![
+			vec![Segment::Text(String::from("a"))],
+			vec![Segment::Interactive(placeholder.clone())]
+		]).unwrap()));
+		let mut code = Code::new("echo hi");
+		code.evaluate();
+		let code = Rc::new(RefCell::new(code));
+		let reference = Rc::new(RefCell::new(Reference::Text(String::from("greeting"), String::from("hi"))));
+		let snippet = Snippet {
+			body: vec![
+				Segment::Text(String::from("Hello ")),
+				Segment::Interactive(choice.clone()),
+				Segment::Reference(reference.clone()),
+				Segment::Interactive(code.clone())
+			],
+			tabs: vec![
+				Tab { num: 1, field: choice },
+				Tab { num: 2, field: placeholder },
+				Tab { num: 0, field: Rc::new(RefCell::new(Placeholder::new(vec![]))) }
+			],
+			program_filled_text: vec![code],
+			references: vec![reference]
+		};
+		let json = serde_json::to_string(&SerializableSnippet(&snippet)).unwrap();
+		assert!(json.contains("\"type\":\"placeholder\""), "{}", json);
+		let copy = serde_json::from_str::<DeserializedSnippet>(&json).unwrap().into_inner();
+		assert_eq!(serde_json::to_string(&SerializableSnippet(&copy)).unwrap(), json);
+		assert_eq!(copy.render(), snippet.render());
+		assert_eq!(copy.tabs.len(), 3);
+		if let Segment::Interactive(choice) = &copy.body[1] {
+			assert!(std::ptr::addr_eq(Rc::as_ptr(choice), Rc::as_ptr(&copy.tabs[0].field)));
+		}
+		if let Segment::Interactive(code) = &copy.body[3] {
+			assert!(std::ptr::addr_eq(Rc::as_ptr(code), Rc::as_ptr(&copy.program_filled_text[0])));
+		}
+		if let Segment::Reference(reference) = &copy.body[2] {
+			assert!(Rc::ptr_eq(reference, &copy.references[0]));
+		}
+	}
+	#[test]
+	fn json_round_trip_shared() {
+		let variable = Rc::new(RefCell::new(Variable::with_resolver("A", |_| String::from("a"))));
+		variable.borrow_mut().evaluate();
+		let placeholder = Rc::new(RefCell::new(Placeholder::new(vec![Segment::text("p")])));
+		let nested = Rc::new(RefCell::new(Placeholder::new(vec![Segment::text("q")])));
+		nested.borrow_mut().default.push(Segment::Interactive(nested.clone()));
+		let reference = Rc::new(RefCell::new(Reference::Text(String::from("key"), String::from("r"))));
+		let snippet = Snippet {
+			body: vec![
+				Segment::Interactive(variable.clone()),
+				Segment::Interactive(variable),
+				Segment::Interactive(placeholder.clone()),
+				Segment::Interactive(placeholder.clone()),
+				Segment::Reference(reference.clone()),
+				Segment::Interactive(nested.clone()),
+				Segment::Reference(reference.clone())
+			],
+			tabs: vec![Tab { num: 1, field: placeholder }, Tab { num: 2, field: nested.clone() }],
+			program_filled_text: vec![],
+			references: vec![reference]
+		};
+		let json = serde_json::to_string(&SerializableSnippet(&snippet)).unwrap();
+		println!("{}", json);
+		assert!(json.contains("{\"type\":\"shared\",\"node\":0}"), "{}", json);
+		assert!(json.contains("{\"type\":\"shared_reference\",\"reference\":0}"), "{}", json);
+		let copy = serde_json::from_str::<DeserializedSnippet>(&json).unwrap().into_inner();
+		assert_eq!(serde_json::to_string(&SerializableSnippet(&copy)).unwrap(), json);
+		assert_eq!(copy.render(), snippet.render());
+		let interactive = |index: usize| match &copy.body[index] {
+			Segment::Interactive(interactive) => Rc::as_ptr(interactive) as *const (),
+			_ => panic!("segment {} is not interactive", index)
+		};
+		assert_eq!(interactive(0), interactive(1));
+		assert_eq!(interactive(2), interactive(3));
+		assert_eq!(Rc::as_ptr(&copy.tabs[0].field) as *const (), interactive(2));
+		assert_eq!(Rc::as_ptr(&copy.tabs[1].field) as *const (), interactive(5));
+		if let (Segment::Reference(first), Segment::Reference(second)) = (&copy.body[4], &copy.body[6]) {
+			assert!(Rc::ptr_eq(first, second));
+			assert!(Rc::ptr_eq(first, &copy.references[0]));
+		}
+		if let Segment::Interactive(copied) = &copy.body[5] {
+			let within = cast_interactive_segment::<Placeholder>(&*copied.borrow()).and_then(|placeholder| match &placeholder.default[1] {
+				Segment::Interactive(within) => Some(Rc::as_ptr(within) as *const ()),
+				_ => None
+			});
+			assert_eq!(within, Some(interactive(5)));
+		}
+		assert!(serde_json::to_string(&*nested.borrow()).is_err());
+		assert!(serde_json::from_str::<Placeholder>("{\"default\":[{\"type\":\"shared\",\"node\":0}]}").is_err());
+	}
+	#[test]
+	fn json_schema_discriminators() {
+		let schema = SerializableSnippet::json_schema();
+		let parsed: serde_json::Value = serde_json::from_str(&schema).unwrap();
+		for discriminator in ["text", "reference", "placeholder", "choice", "variable", "code"] {
+			assert!(schema.contains(&format!("\"const\": \"{}\"", discriminator)), "{}", discriminator);
+		}
+		assert!(parsed["$defs"]["segment"]["oneOf"].is_array());
+	}
+	/// Whether `value` is valid against `schema`, for the keywords the snippet schema uses.
+	fn valid(value: &serde_json::Value, schema: &serde_json::Value, root: &serde_json::Value) -> bool {
+		use serde_json::Value;
+		let schema = match schema {
+			Value::Bool(valid) => return *valid,
+			Value::Object(schema) => schema,
+			_ => panic!("schema is neither an object nor a boolean")
+		};
+		if let Some(Value::String(reference)) = schema.get("$ref") {
+			let name = reference.strip_prefix("#/$defs/").unwrap();
+			return valid(value, &root["$defs"][name], root);
+		}
+		if let Some(types) = schema.get("type") {
+			let is = |kind: &Value| match kind.as_str().unwrap() {
+				"object" => value.is_object(),
+				"array" => value.is_array(),
+				"string" => value.is_string(),
+				"integer" => value.is_u64() || value.is_i64(),
+				"boolean" => value.is_boolean(),
+				"null" => value.is_null(),
+				other => panic!("unexpected type {}", other)
+			};
+			let matches = match types {
+				Value::Array(types) => types.iter().any(is),
+				kind => is(kind)
+			};
+			if !matches {
+				return false;
+			}
+		}
+		if let Some(constant) = schema.get("const") {
+			if value != constant {
+				return false;
+			}
+		}
+		if let Some(Value::Array(options)) = schema.get("enum") {
+			if !options.contains(value) {
+				return false;
+			}
+		}
+		if let (Some(minimum), Some(number)) = (schema.get("minimum"), value.as_f64()) {
+			if number < minimum.as_f64().unwrap() {
+				return false;
+			}
+		}
+		if let Some(Value::Array(options)) = schema.get("oneOf") {
+			if options.iter().filter(|option| valid(value, option, root)).count() != 1 {
+				return false;
+			}
+		}
+		if let Value::Object(object) = value {
+			if let Some(Value::Array(required)) = schema.get("required") {
+				if required.iter().any(|key| !object.contains_key(key.as_str().unwrap())) {
+					return false;
+				}
+			}
+			if let Some(Value::Object(properties)) = schema.get("properties") {
+				for (key, property) in properties {
+					if object.get(key).is_some_and(|field| !valid(field, property, root)) {
+						return false;
+					}
+				}
+			}
+		}
+		if let Value::Array(items) = value {
+			let prefix = match schema.get("prefixItems") {
+				Some(Value::Array(prefix)) => prefix.as_slice(),
+				_ => &[]
+			};
+			for (index, item) in items.iter().enumerate() {
+				let item_schema = match prefix.get(index) {
+					Some(item_schema) => item_schema,
+					None => match schema.get("items") {
+						Some(item_schema) => item_schema,
+						None => continue
+					}
+				};
+				if !valid(item, item_schema, root) {
+					return false;
+				}
+			}
+		}
+		true
+	}
+	#[test]
+	fn json_matches_schema() {
+		let schema: serde_json::Value = serde_json::from_str(&SerializableSnippet::json_schema()).unwrap();
+		#[allow(unused_mut)]
+		let mut transforms = vec![None, Some(VariableTransform::Upcase), Some(VariableTransform::CamelCase)];
+		#[cfg(feature = "regex")]
+		transforms.push(Some(VariableTransform::Regex {
+			pattern: regex::Regex::new("a+").unwrap(),
+			replacement: String::from("b"),
+			global: true
+		}));
+		#[cfg(feature = "chrono")]
+		transforms.push(Some(VariableTransform::DateTime(String::from("%Y"))));
+		let mut body = vec![Segment::Text(String::from("text"))];
+		for transform in transforms {
+			let mut variable = Variable::new("NAME");
+			variable.transform = transform;
+			body.push(Segment::Interactive(Rc::new(RefCell::new(variable))));
+		}
+		let mut code = Code::new("echo hi");
+		code.timeout = Some(Duration::from_millis(1500));
+		code.env.push((String::from("KEY"), String::from("value")));
+		code.last_exit_code = Some(0);
+		let placeholder = Rc::new(RefCell::new(Placeholder::new(vec![Segment::Text(String::from("name"))])));
+		placeholder.borrow_mut().value = Some(vec![Segment::Text(String::from("typed"))]);
+		let reference = Rc::new(RefCell::new(Reference::Text(String::from("key"), String::from("value"))));
+		body.push(Segment::Interactive(Rc::new(RefCell::new(code))));
+		body.push(Segment::Interactive(placeholder.clone()));
+		body.push(Segment::Interactive(Rc::new(RefCell::new(Choice::new(vec![vec![Segment::Interactive(placeholder.clone())], vec![]])))));
+		body.push(Segment::Reference(reference.clone()));
+		let snippet = Snippet {
+			body,
+			tabs: vec![
+				Tab { num: 1, field: placeholder },
+				Tab { num: 0, field: Rc::new(RefCell::new(Placeholder::new(vec![]))) }
+			],
+			program_filled_text: vec![Rc::new(RefCell::new(Variable::new("OTHER")))],
+			references: vec![reference, Rc::new(RefCell::new(Reference::Text(String::from("other"), String::new())))]
+		};
+		let json = serde_json::to_value(SerializableSnippet(&snippet)).unwrap();
+		println!("{}", json);
+		assert!(valid(&json, &schema, &schema));
+		let mut invalid = json.clone();
+		invalid["body"][0]["type"] = serde_json::Value::from("unknown");
+		assert!(!valid(&invalid, &schema, &schema));
+	}
+}